@@ -0,0 +1,358 @@
+use std::rc::Rc;
+use std::hash::BuildHasher;
+use std::collections::HashMap;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use types::{MalVal,MalMap,MalRet,MalErr,ErrKind,func,bigint};
+use types::MalVal::{Nil,Bool,Int,Float,Ratio,Str,Keyword,List,Vector,Hash,LazySeq};
+
+// conversions between rust values and mal values, for embedding
+
+pub trait ToMal {
+  fn to_mal(self) -> MalVal;
+}
+
+pub trait FromMal: Sized {
+  fn from_mal(v: &MalVal) -> Result<Self,MalErr>;
+}
+
+fn expected(what: &str, v: &MalVal) -> MalErr {
+  MalErr::new(ErrKind::Type, &format!("expected {}, got {}", what, v.pr_str(true)))
+}
+
+impl ToMal for MalVal {
+  fn to_mal(self) -> MalVal { self }
+}
+
+impl FromMal for MalVal {
+  fn from_mal(v: &MalVal) -> Result<MalVal,MalErr> { Ok(v.clone()) }
+}
+
+impl ToMal for () {
+  fn to_mal(self) -> MalVal { Nil }
+}
+
+impl ToMal for bool {
+  fn to_mal(self) -> MalVal { Bool(self) }
+}
+
+impl FromMal for bool {
+  fn from_mal(v: &MalVal) -> Result<bool,MalErr> {
+    match v {
+      Bool(b) => Ok(*b),
+      _ => Err(expected("a boolean", v)),
+    }
+  }
+}
+
+// integers that do not fit in an i64 become big integers
+macro_rules! int_conv {
+  ($($t:ty, $to:ident);*) => {$(
+    impl ToMal for $t {
+      fn to_mal(self) -> MalVal {
+        match self.to_i64() {
+          Some(i) => Int(i),
+          None    => bigint(BigInt::from(self)),
+        }
+      }
+    }
+
+    impl FromMal for $t {
+      fn from_mal(v: &MalVal) -> Result<$t,MalErr> {
+        let n = match v {
+          Int(i) => i.$to(),
+          MalVal::BigInt(b) => b.$to(),
+          _ => return Err(expected("an integer", v)),
+        };
+        n.ok_or_else(|| MalErr::new(ErrKind::Arithmetic,
+                                    &format!("{} is out of range for {}",
+                                             v.pr_str(true), stringify!($t))))
+      }
+    }
+  )*};
+}
+
+int_conv!(i8, to_i8; i16, to_i16; i32, to_i32; i64, to_i64; isize, to_isize;
+          u8, to_u8; u16, to_u16; u32, to_u32; u64, to_u64; usize, to_usize);
+
+impl ToMal for f64 {
+  fn to_mal(self) -> MalVal { Float(self) }
+}
+
+impl ToMal for f32 {
+  fn to_mal(self) -> MalVal { Float(self as f64) }
+}
+
+// any number converts to a float
+impl FromMal for f64 {
+  fn from_mal(v: &MalVal) -> Result<f64,MalErr> {
+    match v {
+      Int(i)   => Ok(*i as f64),
+      Float(f) => Ok(*f),
+      MalVal::BigInt(b) => Ok(b.to_f64().unwrap_or(f64::NAN)),
+      Ratio(r) => Ok(r.to_f64().unwrap_or(f64::NAN)),
+      _ => Err(expected("a number", v)),
+    }
+  }
+}
+
+impl FromMal for f32 {
+  fn from_mal(v: &MalVal) -> Result<f32,MalErr> {
+    Ok(f64::from_mal(v)? as f32)
+  }
+}
+
+impl ToMal for String {
+  fn to_mal(self) -> MalVal { Str(self) }
+}
+
+impl ToMal for &str {
+  fn to_mal(self) -> MalVal { Str(self.to_string()) }
+}
+
+impl FromMal for String {
+  fn from_mal(v: &MalVal) -> Result<String,MalErr> {
+    match v {
+      Str(s) => Ok(s.clone()),
+      _ => Err(expected("a string", v)),
+    }
+  }
+}
+
+// None is nil
+impl<T: ToMal> ToMal for Option<T> {
+  fn to_mal(self) -> MalVal {
+    match self {
+      Some(x) => x.to_mal(),
+      None    => Nil,
+    }
+  }
+}
+
+impl<T: FromMal> FromMal for Option<T> {
+  fn from_mal(v: &MalVal) -> Result<Option<T>,MalErr> {
+    match v {
+      Nil => Ok(None),
+      _   => Ok(Some(T::from_mal(v)?)),
+    }
+  }
+}
+
+// a Vec is a list; any sequence, or nil, converts back
+impl<T: ToMal> ToMal for Vec<T> {
+  fn to_mal(self) -> MalVal {
+    list!(self.into_iter().map(ToMal::to_mal).collect::<Vec<MalVal>>())
+  }
+}
+
+impl<T: FromMal> FromMal for Vec<T> {
+  fn from_mal(v: &MalVal) -> Result<Vec<T>,MalErr> {
+    match v {
+      Nil | List(..) | Vector(..) | LazySeq(..) => {
+        v.seq_vec()?.iter().map(T::from_mal).collect()
+      },
+      _ => Err(expected("a sequence", v)),
+    }
+  }
+}
+
+// map keys are strings; keywords convert back to their names
+impl<T: ToMal, S: BuildHasher> ToMal for HashMap<String,T,S> {
+  fn to_mal(self) -> MalVal {
+    let hm: MalMap = self.into_iter().map(|(k, v)| (Str(k), v.to_mal())).collect();
+    Hash(Rc::new(hm), Rc::new(Nil))
+  }
+}
+
+impl<T: FromMal, S: BuildHasher + Default> FromMal for HashMap<String,T,S> {
+  fn from_mal(v: &MalVal) -> Result<HashMap<String,T,S>,MalErr> {
+    match v {
+      Nil => Ok(HashMap::default()),
+      Hash(hm,_) => {
+        hm.iter().map(|(k, v)| {
+          let k = match k {
+            Str(s) => s.clone(),
+            Keyword(k) => k.to_string(),
+            _ => return Err(expected("a string or keyword key", k)),
+          };
+          Ok((k, T::from_mal(v)?))
+        }).collect()
+      },
+      _ => Err(expected("a hash-map", v)),
+    }
+  }
+}
+
+// tuples are vectors
+macro_rules! tuple_conv {
+  ($n:expr; $($t:ident $i:tt),*) => {
+    impl<$($t: ToMal),*> ToMal for ($($t,)*) {
+      fn to_mal(self) -> MalVal {
+        vector!(vec![$(self.$i.to_mal()),*])
+      }
+    }
+
+    impl<$($t: FromMal),*> FromMal for ($($t,)*) {
+      fn from_mal(v: &MalVal) -> Result<($($t,)*),MalErr> {
+        match v.items() {
+          Some(xs) if xs.len() == $n => {
+            let xs: Vec<&MalVal> = xs.collect();
+            Ok(($($t::from_mal(xs[$i])?,)*))
+          },
+          _ => Err(expected(&format!("a sequence of {} items", $n), v)),
+        }
+      }
+    }
+  };
+}
+
+tuple_conv!(1; A 0);
+tuple_conv!(2; A 0, B 1);
+tuple_conv!(3; A 0, B 1, C 2);
+tuple_conv!(4; A 0, B 1, C 2, D 3);
+
+// a rust function of Args that can be called from mal
+pub trait IntoMalFn<Args> {
+  fn into_mal_fn(self, name: &str) -> MalVal;
+}
+
+fn arg<T: FromMal>(name: &str, a: &[MalVal], i: usize) -> Result<T,MalErr> {
+  T::from_mal(&a[i]).map_err(|mut e| {
+    e.msg = format!("{}: argument {}: {}", name, i + 1, e.msg);
+    e
+  })
+}
+
+macro_rules! into_mal_fn {
+  ($n:expr; $($t:ident $i:tt),*) => {
+    impl<F, R, $($t),*> IntoMalFn<($($t,)*)> for F
+      where F: Fn($($t),*) -> Result<R,MalErr> + 'static,
+            R: ToMal, $($t: FromMal),* {
+      #[allow(unused_variables)]
+      fn into_mal_fn(self, name: &str) -> MalVal {
+        let fname = name.to_string();
+        func(name, $n, Some($n), move |a: &[MalVal]| -> MalRet {
+          Ok(self($(arg::<$t>(&fname, a, $i)?),*)?.to_mal())
+        })
+      }
+    }
+  };
+}
+
+into_mal_fn!(0; );
+into_mal_fn!(1; A 0);
+into_mal_fn!(2; A 0, B 1);
+into_mal_fn!(3; A 0, B 1, C 2);
+into_mal_fn!(4; A 0, B 1, C 2, D 3);
+into_mal_fn!(5; A 0, B 1, C 2, D 3, E 4);
+
+// wraps f as a mal function named name that converts its args from mal
+// and its result to mal, e.g. wrap_fn("add", |a: i64, b: i64| Ok(a + b))
+pub fn wrap_fn<Args, F: IntoMalFn<Args>>(name: &str, f: F) -> MalVal {
+  f.into_mal_fn(name)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use super::*;
+  use types::{ErrKind,keyword_map};
+  use types::MalVal::Sym;
+
+  fn round_trip<T: ToMal + FromMal + Clone + PartialEq + ::std::fmt::Debug>(x: T) {
+    assert_eq!(T::from_mal(&x.clone().to_mal()).unwrap(), x);
+  }
+
+  #[test]
+  fn primitives() {
+    round_trip(true);
+    round_trip(-5i8);
+    round_trip(7u16);
+    round_trip(i64::MIN);
+    round_trip(u64::MAX);
+    round_trip(1.5f64);
+    round_trip("héllo".to_string());
+    assert_eq!(u64::MAX.to_mal().pr_str(true), "18446744073709551615N");
+    assert_eq!(f64::from_mal(&Int(3)).unwrap(), 3.0);
+    assert_eq!(().to_mal(), Nil);
+  }
+
+  #[test]
+  fn out_of_range() {
+    let e = u8::from_mal(&Int(256)).unwrap_err();
+    assert_eq!(e.kind, ErrKind::Arithmetic);
+    assert_eq!(e.msg, "256 is out of range for u8");
+    let e = i32::from_mal(&Str("1".to_string())).unwrap_err();
+    assert_eq!(e.kind, ErrKind::Type);
+    assert_eq!(e.msg, "expected an integer, got \"1\"");
+  }
+
+  #[test]
+  fn vecs() {
+    round_trip(vec![1i64, 2, 3]);
+    round_trip(Vec::<String>::new());
+    assert_eq!(vec![1i64, 2].to_mal().pr_str(true), "(1 2)");
+    assert_eq!(Vec::<i64>::from_mal(&vector![Int(4), Int(5)]).unwrap(), vec![4, 5]);
+    assert_eq!(Vec::<i64>::from_mal(&Nil).unwrap(), Vec::<i64>::new());
+    assert!(Vec::<i64>::from_mal(&list![Int(1), Str("x".to_string())]).is_err());
+    assert!(Vec::<i64>::from_mal(&Int(1)).is_err());
+  }
+
+  #[test]
+  fn hash_maps() {
+    let mut m = HashMap::new();
+    m.insert("a".to_string(), 1i64);
+    m.insert("b".to_string(), 2i64);
+    round_trip(m.clone());
+    let kw = keyword_map(vec![("a", Int(1)), ("b", Int(2))]);
+    assert_eq!(HashMap::<String,i64>::from_mal(&kw).unwrap(), m);
+    assert!(HashMap::<String,i64>::from_mal(&list![]).is_err());
+  }
+
+  #[test]
+  fn options_and_tuples() {
+    round_trip(Some(3i64));
+    round_trip(None::<i64>);
+    round_trip(Some(vec![Some(1i64), None]));
+    assert_eq!(None::<i64>.to_mal(), Nil);
+    round_trip((1i64, "x".to_string(), false));
+    assert!(<(i64, i64)>::from_mal(&vector!(vec![Int(1)])).is_err());
+  }
+
+  #[test]
+  fn wrap_fn_calls() {
+    let add = wrap_fn("add", |a: i64, b: i64| Ok(a + b));
+    assert_eq!(add.apply(vec![Int(1), Int(2)]).unwrap(), Int(3));
+    let greet = wrap_fn("greet", |n: Option<String>| -> Result<String,MalErr> {
+      Ok(format!("hi {}", n.unwrap_or_else(|| "there".to_string())))
+    });
+    assert_eq!(greet.apply(vec![Nil]).unwrap(), Str("hi there".to_string()));
+    let fail = wrap_fn("fail", || -> Result<(),MalErr> { Err(MalErr::new(ErrKind::Io, "no")) });
+    assert_eq!(fail.apply(vec![]).unwrap_err().kind, ErrKind::Io);
+  }
+
+  #[test]
+  fn wrap_fn_arity() {
+    let add = wrap_fn("add", |a: i64, b: i64| Ok(a + b));
+    for args in [vec![], vec![Int(1)], vec![Int(1), Int(2), Int(3)]] {
+      let e = add.apply(args).unwrap_err();
+      assert_eq!(e.kind, ErrKind::Arity);
+      assert!(e.msg.contains("add"), "{}", e.msg);
+    }
+  }
+
+  #[test]
+  fn wrap_fn_arg_types() {
+    let add = wrap_fn("add", |a: i64, b: i64| Ok(a + b));
+    let e = add.apply(vec![Int(1), Sym("x".to_string())]).unwrap_err();
+    assert_eq!(e.kind, ErrKind::Type);
+    assert_eq!(e.msg, "add: argument 2: expected an integer, got x");
+    let small = wrap_fn("small", |a: u8| Ok(a));
+    let e = small.apply(vec![Int(300)]).unwrap_err();
+    assert_eq!(e.kind, ErrKind::Arithmetic);
+    assert_eq!(e.msg, "small: argument 1: 300 is out of range for u8");
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
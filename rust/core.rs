@@ -1,88 +1,175 @@
 use std::rc::Rc;
+use std::cmp::Ordering;
+use std::cell::RefCell;
 use std::fs::File;
-use std::io::Read;
-use std::sync::Mutex;
+use std::io;
+use std::io::{Read,Write};
+use std::env;
+use std::process;
+use std::process::{Command,Stdio};
+use std::thread;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use types::{MalVal,MalArgs,MalRet,error,func,hash_map,_assoc,_dissoc,atom};
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector,Hash,Func,MalFunc,Atom};
-use types::MalErr::{ErrMalVal};
-use reader::read_str;
+use types::{MalVal,MalList,MalMap,MalSet,MalRet,MalErr,ErrKind,error,error_kind,func,keyword_map,hash_map,hash_set,lazy_seq,lazy_cons,_assoc,_dissoc,atom};
+use types::MalVal::{Nil,Bool,Int,Float,Str,Keyword,Sym,List,Vector,Hash,Set,LazySeq,Func,MalFunc,Atom};
+use reader::{read_str,read_all,read_edn};
 use printer::pr_seq;
-
-macro_rules! fn_t_int_int {
-  ($ret:ident, $fn:expr) => {{
-    |a:MalArgs| {
-      match (a[0].clone(), a[1].clone()) {
-        (Int(a0), Int(a1)) => Ok($ret($fn(a0, a1))),
-        _ => error("expecting (int,int) args"),
-      }
-    }
-  }};
-}
+use numeric;
+use files;
+use regexp;
+use strings;
+use json;
 
 macro_rules! fn_is_type {
   ($($ps:pat),*) => {{
-    |a:MalArgs| { Ok(Bool(match a[0] { $($ps => true,)* _ => false})) }
+    |a: &[MalVal]| { Ok(Bool(match a[0] { $($ps => true,)* _ => false})) }
   }};
   ($p:pat if $e:expr) => {{
-    |a:MalArgs| { Ok(Bool(match a[0] { $p if $e => true, _ => false})) }
+    |a: &[MalVal]| { Ok(Bool(match a[0] { $p if $e => true, _ => false})) }
   }};
   ($p:pat if $e:expr,$($ps:pat),*) => {{
-    |a:MalArgs| { Ok(Bool(match a[0] { $p if $e => true, $($ps => true,)* _ => false})) }
+    |a: &[MalVal]| { Ok(Bool(match a[0] { $p if $e => true, $($ps => true,)* _ => false})) }
   }};
 }
 
 macro_rules! fn_str {
   ($fn:expr) => {{
-    |a:MalArgs| {
+    |a: &[MalVal]| {
       match a[0].clone() {
         Str(a0) => $fn(a0),
-        _ => error("expecting (str) arg"),
+        _ => error_kind(ErrKind::Type, "expecting (str) arg"),
       }
     }
   }};
 }
 
-fn symbol(a: MalArgs) -> MalRet {
-  match a[0] {
-    Str(ref s) => Ok(Sym(s.to_string())),
-    _ => error("illegal symbol call")
+// an ns entry checked to take between min and max args; `_` as the
+// max means any number
+macro_rules! native {
+  ($name:expr, $min:expr, _, $f:expr) => {{
+    ($name, func($name, $min, None, $f))
+  }};
+  ($name:expr, $min:expr, $max:expr, $f:expr) => {{
+    ($name, func($name, $min, Some($max), $f))
+  }};
+}
+
+// (keyword name) or (keyword ns name)
+fn keyword(a: &[MalVal]) -> MalRet {
+  match (&a[0], a.get(1)) {
+    (_, None) => a[0].keyword(),
+    (Nil, Some(Str(name))) => Ok(::types::keyword(name)),
+    (Str(ns), Some(Str(name))) => Ok(::types::keyword(&format!("{}/{}", ns, name))),
+    _ => error_kind(ErrKind::Type, "keyword: expected strings"),
   }
 }
 
-fn readline(a: MalArgs) -> MalRet {
-  lazy_static! {
-    static ref RL: Mutex<Editor<()>> = Mutex::new(Editor::<()>::new());
+fn symbol(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Str(ref s) => Ok(Sym(s.to_string())),
+    _ => error_kind(ErrKind::Type, "illegal symbol call")
   }
-  //let mut rl = Editor::<()>::new();
+}
 
+fn readline(rl: &RefCell<Editor<()>>, a: &[MalVal]) -> MalRet {
   match a[0] {
     Str(ref p) => {
-      //match rl.readline(p) {
-      match RL.lock().unwrap().readline(p) {
+      match rl.borrow_mut().readline(p) {
         Ok(line)                => Ok(Str(line)),
         Err(ReadlineError::Eof) => Ok(Nil),
-        Err(e)                  => error(&format!("{:?}", e))
+        Err(e)                  => error_kind(ErrKind::Io, &format!("{:?}", e))
       }
     },
-    _ => error("readline: prompt is not Str"),
+    _ => error_kind(ErrKind::Type, "readline: prompt is not Str"),
   }
 }
 
-fn slurp(f: String) -> MalRet {
+pub fn read_file(f: &str) -> Result<String,MalErr> {
   let mut s = String::new();
   match File::open(f).and_then(|mut f| f.read_to_string(&mut s)) {
-    Ok(_) => Ok(Str(s)),
-    Err(e) => error(&e.to_string()),
+    Ok(_) => Ok(s),
+    Err(e) => Err(files::io_error(f, e)),
+  }
+}
+
+fn slurp(f: String) -> MalRet {
+  Ok(Str(read_file(&f)?))
+}
+
+// the forms in file f, with f kept as the source of their spans
+fn read_forms(f: String) -> MalRet {
+  Ok(list!(read_all(read_file(&f)?, Some(&f))?))
+}
+
+// whether the flag key is set in an options map; no map means no flags
+pub fn flag(opts: Option<&MalVal>, key: &str) -> Result<bool,MalErr> {
+  match opts {
+    None | Some(Nil) => Ok(false),
+    Some(Hash(hm,_)) => Ok(hm.get(&::types::keyword(key)).is_some_and(truthy)),
+    Some(o) => Err(MalErr::new(ErrKind::Type,
+                               &format!("expected an options map, got {}", o.pr_str(true)))),
+  }
+}
+
+// (edn/read-string s) or (edn/read-string opts s), where opts may give
+// :readers, a map of tag symbols to functions, and a :default function
+// of the tag and value for other tags
+fn edn_read_string(a: &[MalVal]) -> MalRet {
+  let (opts, s) = match a.len() {
+    1 => (Nil, &a[0]),
+    _ => (a[0].clone(), &a[1]),
+  };
+  let (readers, default) = match opts {
+    Nil => (Nil, Nil),
+    Hash(ref hm,_) => {
+      let opt = |k| hm.get(&::types::keyword(k)).cloned().unwrap_or(Nil);
+      (opt("readers"), opt("default"))
+    },
+    _ => return error_kind(ErrKind::Type, &format!("expected an options map, got {}",
+                                                   opts.pr_str(true))),
+  };
+  match s {
+    Str(s) => read_edn(s.to_string(), readers, default),
+    _ => error_kind(ErrKind::Type, &format!("edn/read-string: expected a string, got {}",
+                                            s.pr_str(true))),
+  }
+}
+
+// fails unless v is made only of values that edn can represent
+fn check_edn(v: &MalVal) -> Result<(),MalErr> {
+  match v {
+    List(l,_) => l.iter().try_for_each(check_edn),
+    Vector(l,_) => l.iter().try_for_each(check_edn),
+    Set(hs,_) => hs.iter().try_for_each(check_edn),
+    Hash(hm,_) => hm.iter().try_for_each(|(k, v)| { check_edn(k)?; check_edn(v) }),
+    LazySeq(..) => v.seq_vec()?.iter().try_for_each(check_edn),
+    MalVal::Tagged(_,v) => check_edn(v),
+    Func(..) | MalFunc{..} | Atom(_) | MalVal::Regex(_) | MalVal::Reduced(_) => {
+      Err(MalErr::new(ErrKind::Type,
+                      &format!("edn/pr-str: cannot write {} as edn", v.pr_str(true))))
+    },
+    _ => Ok(()),
   }
 }
 
-fn time_ms(_a: MalArgs) -> MalRet {
+fn edn_pr_str(a: &[MalVal]) -> MalRet {
+  a.iter().try_for_each(check_edn)?;
+  Ok(Str(pr_seq(a, true, "", "", " ")?))
+}
+
+fn tagged_literal(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Sym(ref tag) => Ok(MalVal::Tagged(Rc::from(&tag[..]), Rc::new(a[1].clone()))),
+    _ => error_kind(ErrKind::Type, &format!("tagged-literal: expected a tag symbol, got {}",
+                                            a[0].pr_str(true))),
+  }
+}
+
+fn time_ms(_a: &[MalVal]) -> MalRet {
   let ms_e = match SystemTime::now().duration_since(UNIX_EPOCH) {
     Ok(d) => d,
     Err(e) => return error(&format!("{:?}", e)),
@@ -91,235 +178,1054 @@ fn time_ms(_a: MalArgs) -> MalRet {
          ms_e.subsec_nanos() as i64 / 1_000_000))
 }
 
-fn get(a: MalArgs) -> MalRet {
+pub fn str_arg<'a>(name: &str, v: &'a MalVal) -> Result<&'a str,MalErr> {
+  match v {
+    Str(s) => Ok(s),
+    _ => Err(MalErr::new(ErrKind::Type,
+                         &format!("{}: expected a string, got {}", name, v.pr_str(true)))),
+  }
+}
+
+// (sh cmd arg*) or (sh cmd arg* opts), where opts may give :in, a string
+// written to the command's stdin, :dir, its working directory, and :env,
+// a map of extra environment variables; returns {:exit :out :err}, with
+// a nil :exit when the command was killed by a signal
+fn sh(a: &[MalVal]) -> MalRet {
+  let (opts, cmd) = match a.split_last() {
+    Some((Hash(hm,_), cmd)) if !cmd.is_empty() => (Some(hm.clone()), cmd),
+    _ => (None, a),
+  };
+  let prog = str_arg("sh", &cmd[0])?;
+  let mut c = Command::new(prog);
+  for x in cmd[1..].iter() {
+    c.arg(str_arg("sh", x)?);
+  }
+  let opt = |k| opts.as_ref().and_then(|hm| hm.get(&::types::keyword(k)).cloned());
+  if let Some(dir) = opt("dir") {
+    c.current_dir(str_arg("sh", &dir)?);
+  }
+  match opt("env") {
+    None | Some(Nil) => (),
+    Some(Hash(env,_)) => for (k, v) in env.iter() {
+      let k = match k {
+        Keyword(k) => k.to_string(),
+        _ => str_arg("sh", k)?.to_string(),
+      };
+      c.env(k, str_arg("sh", v)?);
+    },
+    Some(e) => return error_kind(ErrKind::Type, &format!("sh: expected an :env map, got {}",
+                                                         e.pr_str(true))),
+  }
+  let input = match opt("in") {
+    None | Some(Nil) => None,
+    Some(i) => Some(str_arg("sh", &i)?.to_string()),
+  };
+  c.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() });
+  c.stdout(Stdio::piped()).stderr(Stdio::piped());
+  let mut child = c.spawn().map_err(|e| files::io_error(prog, e))?;
+  // written from another thread so a command that fills its stdout
+  // before reading all of stdin can't deadlock
+  let writer = match (input, child.stdin.take()) {
+    (Some(input), Some(mut stdin)) => {
+      Some(thread::spawn(move || { let _ = stdin.write_all(input.as_bytes()); }))
+    },
+    _ => None,
+  };
+  let out = child.wait_with_output().map_err(|e| files::io_error(prog, e))?;
+  if let Some(w) = writer {
+    let _ = w.join();
+  }
+  Ok(keyword_map(vec![
+    ("exit", out.status.code().map_or(Nil, |c| Int(c as i64))),
+    ("out", Str(String::from_utf8_lossy(&out.stdout).into_owned())),
+    ("err", Str(String::from_utf8_lossy(&out.stderr).into_owned())),
+  ]))
+}
+
+// (getenv name) is the variable's value or nil; (getenv) is a map of
+// every variable
+fn getenv(a: &[MalVal]) -> MalRet {
+  match a.first() {
+    None => {
+      let hm: MalMap = env::vars().map(|(k, v)| (Str(k), Str(v))).collect();
+      Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+    },
+    Some(k) => Ok(env::var(str_arg("getenv", k)?).map_or(Nil, Str)),
+  }
+}
+
+// sets a variable for this process and the commands it runs; a nil
+// value unsets it
+fn setenv(a: &[MalVal]) -> MalRet {
+  let k = str_arg("setenv", &a[0])?;
+  if k.is_empty() || k.contains('=') || k.contains('\0') {
+    return error_kind(ErrKind::Type, &format!("setenv: invalid name {}", a[0].pr_str(true)));
+  }
+  match a[1] {
+    Nil => env::remove_var(k),
+    ref v => env::set_var(k, str_arg("setenv", v)?),
+  }
+  Ok(Nil)
+}
+
+fn exit(a: &[MalVal]) -> MalRet {
+  let code = match a.first() {
+    None => 0,
+    Some(Int(c)) if *c >= i32::MIN as i64 && *c <= i32::MAX as i64 => *c as i32,
+    Some(c) => return error_kind(ErrKind::Type, &format!("exit: invalid status {}",
+                                                         c.pr_str(true))),
+  };
+  let _ = io::stdout().flush();
+  process::exit(code)
+}
+
+// splits command line args into {:opts :args}: --name=value and --name
+// (as true) become :name options, -abc sets each of :a :b :c, and
+// everything else, or everything after "--", is a plain arg
+fn parse_args(a: &[MalVal]) -> MalRet {
+  let mut opts = MalMap::default();
+  let mut args = vec![];
+  let mut rest = false;
+  for x in a[0].seq_vec()?.iter() {
+    let s = str_arg("parse-args", x)?;
+    if rest || s == "-" || !s.starts_with('-') {
+      args.push(x.clone());
+    } else if s == "--" {
+      rest = true;
+    } else if let Some(long) = s.strip_prefix("--") {
+      let (k, v) = match long.find('=') {
+        Some(i) => (&long[..i], Str(long[i+1..].to_string())),
+        None    => (long, Bool(true)),
+      };
+      opts.insert(::types::keyword(k), v);
+    } else {
+      for c in s[1..].chars() {
+        opts.insert(::types::keyword(&c.to_string()), Bool(true));
+      }
+    }
+  }
+  Ok(keyword_map(vec![("opts", Hash(Rc::new(opts), Rc::new(Nil))),
+                      ("args", vector!(args))]))
+}
+
+fn get(a: &[MalVal]) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
     (Nil, _) => Ok(Nil),
-    (Hash(ref hm,_), Str(ref s)) => {
-      match hm.get(s) {
+    (Hash(ref hm,_), ref k) => {
+      match hm.get(k) {
         Some(mv) => Ok(mv.clone()),
         None     => Ok(Nil),
       }
     },
-    _ => error("illegal get args")
+    (Set(ref hs,_), ref k) => Ok(if hs.contains(k) { k.clone() } else { Nil }),
+    _ => error_kind(ErrKind::Type, "illegal get args")
   }
 }
 
-fn assoc(a: MalArgs) -> MalRet {
+fn assoc(a: &[MalVal]) -> MalRet {
   match a[0] {
     Hash(ref hm,_) => _assoc((**hm).clone(), a[1..].to_vec()),
-    _ => error("assoc on non-Hash Map")
+    _ => error_kind(ErrKind::Type, "assoc on non-Hash Map")
   }
 }
 
-fn dissoc(a: MalArgs) -> MalRet {
+fn dissoc(a: &[MalVal]) -> MalRet {
   match a[0] {
     Hash(ref hm,_) => _dissoc((**hm).clone(), a[1..].to_vec()),
-    _ => error("dissoc on non-Hash Map")
+    _ => error_kind(ErrKind::Type, "dissoc on non-Hash Map")
   }
 }
 
-fn contains_q(a: MalArgs) -> MalRet {
+fn contains_q(a: &[MalVal]) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
-    (Hash(ref hm,_), Str(ref s)) => {
-      Ok(Bool(hm.contains_key(s)))
+    (Hash(ref hm,_), ref k) => {
+      Ok(Bool(hm.contains_key(k)))
     },
-    _ => error("illegal get args")
+    (Set(ref hs,_), ref k) => Ok(Bool(hs.contains(k))),
+    _ => error_kind(ErrKind::Type, "illegal get args")
   }
 }
 
-fn keys(a: MalArgs) -> MalRet {
+fn keys(a: &[MalVal]) -> MalRet {
   match a[0] {
     Hash(ref hm,_) => {
-      Ok(list!(hm.keys().map(|k|{Str(k.to_string())}).collect()))
+      Ok(list!(hm.keys().cloned().collect::<MalList>()))
     },
-    _ => error("keys requires Hash Map")
+    _ => error_kind(ErrKind::Type, "keys requires Hash Map")
   }
 }
 
-fn vals(a: MalArgs) -> MalRet {
+fn vals(a: &[MalVal]) -> MalRet {
   match a[0] {
     Hash(ref hm,_) => {
-      Ok(list!(hm.values().map(|v|{v.clone()}).collect()))
+      Ok(list!(hm.values().cloned().collect::<MalList>()))
     },
-    _ => error("keys requires Hash Map")
+    _ => error_kind(ErrKind::Type, "keys requires Hash Map")
   }
 }
 
-fn cons(a: MalArgs) -> MalRet {
-  match a[1].clone() {
-    List(v,_) | Vector(v,_) => {
-      let mut new_v = vec![a[0].clone()];
-      new_v.extend_from_slice(&v);
-      Ok(list!(new_v.to_vec()))
+fn cons(a: &[MalVal]) -> MalRet {
+  match a[1] {
+    List(ref l,_) => Ok(list!(l.cons(a[0].clone()))),
+    Vector(ref v,_) => {
+      let l: MalList = v.iter().cloned().collect();
+      Ok(list!(l.cons(a[0].clone())))
     },
-    _ => error("cons expects seq as second arg"),
+    Nil => Ok(list!(vec![a[0].clone()])),
+    LazySeq(..) => Ok(lazy_cons(a[0].clone(), a[1].clone())),
+    _ => error_kind(ErrKind::Type, "cons expects seq as second arg"),
   }
 }
 
-fn concat(a: MalArgs) -> MalRet {
-  let mut new_v = vec![];
-  for seq in a.iter() {
-    match seq {
-      List(v,_) | Vector(v,_) => new_v.extend_from_slice(v),
-      _ => return error("non-seq passed to concat"),
+// concatenates lazily once any of the seqs is lazy, so that concat in
+// quasiquoted code still builds a list
+fn concat(a: &[MalVal]) -> MalRet {
+  if a.iter().any(|seq| matches!(seq, LazySeq(..))) {
+    return Ok(lazy_concat(a.to_vec()));
+  }
+  // the last list is shared rather than copied
+  let (tail, init) = match a.split_last() {
+    Some((List(l,_), init)) => ((**l).clone(), init),
+    _ => (MalList::new(), a),
+  };
+  let mut items = vec![];
+  for seq in init.iter() {
+    match seq.items() {
+      Some(xs) => items.extend(xs.cloned()),
+      None => return error_kind(ErrKind::Type, "non-seq passed to concat"),
     }
   }
-  Ok(list!(new_v.to_vec()))
+  Ok(list!(MalList::prepend(tail, items)))
+}
+
+fn lazy_concat(seqs: Vec<MalVal>) -> MalVal {
+  lazy_seq(move || {
+    match seqs.split_first() {
+      None => Ok(Nil),
+      Some((seq, more)) => match seq.first_rest()? {
+        Some((x, rest)) => {
+          let mut seqs = vec![rest];
+          seqs.extend_from_slice(more);
+          Ok(lazy_cons(x, lazy_concat(seqs)))
+        },
+        None => Ok(lazy_concat(more.to_vec())),
+      },
+    }
+  })
 }
 
-fn nth(a: MalArgs) -> MalRet {
+fn nth(a: &[MalVal]) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
-    (List(seq,_), Int(idx)) | (Vector(seq,_), Int(idx)) => {
-      if seq.len() <= idx as usize { 
-        return error("nth: index out of range");
+    (LazySeq(..), Int(idx)) if idx >= 0 => {
+      let mut s = a[0].clone();
+      for _ in 0..idx {
+        s = match s.first_rest()? {
+          Some((_, rest)) => rest,
+          None => return error("nth: index out of range"),
+        };
+      }
+      match s.first_rest()? {
+        Some((x, _)) => Ok(x),
+        None => error("nth: index out of range"),
+      }
+    },
+    (Str(s), Int(idx)) if idx >= 0 => match s.chars().nth(idx as usize) {
+      Some(c) => Ok(Str(c.to_string())),
+      None => error("nth: index out of range"),
+    },
+    (List(..), Int(idx)) | (Vector(..), Int(idx)) if idx >= 0 => {
+      match a[0].items().and_then(|mut xs| xs.nth(idx as usize)) {
+        Some(x) => Ok(x.clone()),
+        None => error("nth: index out of range"),
       }
-      Ok(seq[idx as usize].clone())
     }
-    _ => error("invalid args to nth"),
+    _ => error_kind(ErrKind::Type, "invalid args to nth"),
   }
 }
 
-fn first(a: MalArgs) -> MalRet {
-  match a[0].clone() {
-    List(ref seq,_) | Vector(ref seq,_) if seq.len() == 0 => Ok(Nil),
-    List(ref seq,_) | Vector(ref seq,_) => Ok(seq[0].clone()),
-    Nil => Ok(Nil),
-    _ => error("invalid args to first"),
-  }
+fn first(a: &[MalVal]) -> MalRet {
+  Ok(a[0].first_rest()?.map_or(Nil, |(x, _)| x))
 }
 
-fn rest(a: MalArgs) -> MalRet {
-  match a[0].clone() {
-    List(ref seq,_) | Vector(ref seq,_) => {
-      if seq.len() > 1 {
-        Ok(list!(seq[1..].to_vec()))
-      } else {
-        Ok(list![])
-      }
-    },
-    Nil => Ok(list![]),
-    _ => error("invalid args to first"),
+// a list, as mal defines rest, except that the rest of a lazy seq stays
+// lazy; the lazy rest first_rest gives a vector, string, map or set is
+// made a list once here, so walking the result with rest stays linear
+fn rest(a: &[MalVal]) -> MalRet {
+  match a[0].first_rest()? {
+    Some((_, rest @ LazySeq(..))) if matches!(a[0], LazySeq(..)) => Ok(rest),
+    Some((_, rest @ List(..))) => Ok(rest),
+    Some((_, rest)) => Ok(list!(rest.seq_vec()?)),
+    None => Ok(list![]),
   }
 }
 
-fn apply(a: MalArgs) -> MalRet {
+fn apply(a: &[MalVal]) -> MalRet {
   match a[a.len()-1] {
-    List(ref v,_) | Vector(ref v,_) => {
+    List(_,_) | Vector(_,_) | LazySeq(_,_) | Nil => {
       let f = &a[0];
       let mut fargs = a[1..a.len()-1].to_vec();
-      fargs.extend_from_slice(&v);
+      fargs.extend(a[a.len()-1].seq_vec()?);
       f.apply(fargs)
     },
-    _ => error("apply called with non-seq"),
+    _ => error_kind(ErrKind::Type, "apply called with non-seq"),
   }
 }
 
-fn map(a: MalArgs) -> MalRet {
+// maps lazily over a lazy seq, and eagerly over anything else; with
+// several colls, f takes an item of each and the result stops at the
+// shortest
+fn map(a: &[MalVal]) -> MalRet {
+  if a.len() > 2 {
+    let m = lazy_map_n(a[0].clone(), a[1..].to_vec());
+    return match a[1..].iter().any(|s| matches!(s, LazySeq(..))) {
+      true  => Ok(m),
+      false => Ok(list!(m.seq_vec()?)),
+    };
+  }
   match a[1] {
-    List(ref v,_) | Vector(ref v,_) => {
+    LazySeq(..) => Ok(lazy_map(a[0].clone(), a[1].clone())),
+    List(..) | Vector(..) => {
       let mut res = vec![];
-      for mv in v.iter() {
-        res.push(a[0].apply(vec![mv.clone()])?)
+      for mv in a[1].seq_vec()? {
+        res.push(a[0].apply(vec![mv])?)
       }
       Ok(list!(res))
     },
-    _ => error("map called with non-seq"),
+    ref s => Ok(list!(lazy_map(a[0].clone(), s.clone()).seq_vec()?)),
+  }
+}
+
+fn lazy_map(f: MalVal, seq: MalVal) -> MalVal {
+  lazy_seq(move || {
+    match seq.first_rest()? {
+      Some((x, rest)) => Ok(lazy_cons(f.apply(vec![x])?, lazy_map(f.clone(), rest))),
+      None => Ok(Nil),
+    }
+  })
+}
+
+fn lazy_map_n(f: MalVal, seqs: Vec<MalVal>) -> MalVal {
+  lazy_seq(move || {
+    let mut args = vec![];
+    let mut rests = vec![];
+    for s in seqs.iter() {
+      match s.first_rest()? {
+        Some((x, rest)) => { args.push(x); rests.push(rest); },
+        None => return Ok(Nil),
+      }
+    }
+    Ok(lazy_cons(f.apply(args)?, lazy_map_n(f.clone(), rests)))
+  })
+}
+
+fn lazy_seq_fn(a: &[MalVal]) -> MalRet {
+  let f = a[0].clone();
+  Ok(lazy_seq(move || f.apply(vec![])))
+}
+
+fn iterate(a: &[MalVal]) -> MalRet {
+  fn iterate_from(f: MalVal, x: MalVal) -> MalVal {
+    let next = x.clone();
+    lazy_cons(x, lazy_seq(move || Ok(iterate_from(f.clone(), f.apply(vec![next.clone()])?))))
+  }
+  Ok(iterate_from(a[0].clone(), a[1].clone()))
+}
+
+// (range), (range end), (range start end) or (range start end step)
+fn range(a: &[MalVal]) -> MalRet {
+  fn range_from(start: MalVal, end: Option<MalVal>, step: MalVal) -> MalVal {
+    lazy_seq(move || {
+      if let Some(ref end) = end {
+        let more = match numeric::lt(&[step.clone(), Int(0)])? {
+          Bool(true) => numeric::gt(&[start.clone(), end.clone()])?,
+          _          => numeric::lt(&[start.clone(), end.clone()])?,
+        };
+        if more == Bool(false) {
+          return Ok(Nil);
+        }
+      }
+      let next = numeric::add(&[start.clone(), step.clone()])?;
+      Ok(lazy_cons(start.clone(), range_from(next, end.clone(), step.clone())))
+    })
+  }
+  let (start, end, step) = match a.len() {
+    0 => (Int(0), None, Int(1)),
+    1 => (Int(0), Some(a[0].clone()), Int(1)),
+    2 => (a[0].clone(), Some(a[1].clone()), Int(1)),
+    _ => (a[0].clone(), Some(a[1].clone()), a[2].clone()),
+  };
+  // fails unless every bound is a number
+  numeric::lt(a)?;
+  Ok(range_from(start, end, step))
+}
+
+// (repeat x) or (repeat n x)
+fn repeat(a: &[MalVal]) -> MalRet {
+  fn repeat_forever(x: MalVal) -> MalVal {
+    let rest = x.clone();
+    lazy_cons(x, lazy_seq(move || Ok(repeat_forever(rest.clone()))))
+  }
+  match a.len() {
+    1 => Ok(repeat_forever(a[0].clone())),
+    _ => take(&[a[0].clone(), repeat_forever(a[1].clone())]),
+  }
+}
+
+fn count_arg(a: &MalVal, name: &str) -> Result<i64,MalErr> {
+  match a {
+    Int(n) => Ok(*n),
+    _ => Err(MalErr::new(ErrKind::Type, &format!("{}: expected an int count", name))),
+  }
+}
+
+fn take(a: &[MalVal]) -> MalRet {
+  fn take_seq(n: i64, seq: MalVal) -> MalVal {
+    lazy_seq(move || {
+      if n <= 0 {
+        return Ok(Nil);
+      }
+      match seq.first_rest()? {
+        Some((x, rest)) => Ok(lazy_cons(x, take_seq(n - 1, rest))),
+        None => Ok(Nil),
+      }
+    })
+  }
+  Ok(take_seq(count_arg(&a[0], "take")?, a[1].clone()))
+}
+
+fn drop(a: &[MalVal]) -> MalRet {
+  let (n, seq) = (count_arg(&a[0], "drop")?, a[1].clone());
+  Ok(lazy_seq(move || {
+    let mut s = seq.clone();
+    for _ in 0..n {
+      s = match s.first_rest()? {
+        Some((_, rest)) => rest,
+        None => return Ok(Nil),
+      };
+    }
+    Ok(s)
+  }))
+}
+
+fn take_while(a: &[MalVal]) -> MalRet {
+  fn take_while_seq(pred: MalVal, seq: MalVal) -> MalVal {
+    lazy_seq(move || {
+      match seq.first_rest()? {
+        Some((x, rest)) if truthy(&pred.apply(vec![x.clone()])?) => {
+          Ok(lazy_cons(x, take_while_seq(pred.clone(), rest)))
+        },
+        _ => Ok(Nil),
+      }
+    })
+  }
+  Ok(take_while_seq(a[0].clone(), a[1].clone()))
+}
+
+// the items of seq for which pred is truthy, or with keep false, those
+// for which it is not
+fn filter_seq(pred: MalVal, seq: MalVal, keep: bool) -> MalVal {
+  lazy_seq(move || {
+    let mut s = seq.clone();
+    while let Some((x, rest)) = s.first_rest()? {
+      if truthy(&pred.apply(vec![x.clone()])?) == keep {
+        return Ok(lazy_cons(x, filter_seq(pred.clone(), rest, keep)));
+      }
+      s = rest;
+    }
+    Ok(Nil)
+  })
+}
+
+fn filter(a: &[MalVal]) -> MalRet {
+  Ok(filter_seq(a[0].clone(), a[1].clone(), true))
+}
+
+fn remove(a: &[MalVal]) -> MalRet {
+  Ok(filter_seq(a[0].clone(), a[1].clone(), false))
+}
+
+// the non-nil results of f on each item
+fn keep(a: &[MalVal]) -> MalRet {
+  fn keep_seq(f: MalVal, seq: MalVal) -> MalVal {
+    lazy_seq(move || {
+      let mut s = seq.clone();
+      while let Some((x, rest)) = s.first_rest()? {
+        match f.apply(vec![x])? {
+          Nil => s = rest,
+          y   => return Ok(lazy_cons(y, keep_seq(f.clone(), rest))),
+        }
+      }
+      Ok(Nil)
+    })
+  }
+  Ok(keep_seq(a[0].clone(), a[1].clone()))
+}
+
+// (reduce f coll) or (reduce f init coll); f may return (reduced x) to
+// stop early with x
+fn reduce(a: &[MalVal]) -> MalRet {
+  let f = &a[0];
+  let (mut acc, mut s) = match a.len() {
+    2 => match a[1].first_rest()? {
+      Some((x, rest)) => (x, rest),
+      None => return f.apply(vec![]),
+    },
+    _ => (a[1].clone(), a[2].clone()),
+  };
+  while let Some((x, rest)) = s.first_rest()? {
+    acc = f.apply(vec![acc, x])?;
+    if let MalVal::Reduced(v) = acc {
+      return Ok((*v).clone());
+    }
+    s = rest;
+  }
+  Ok(acc)
+}
+
+// the first truthy result of pred on an item, or nil
+fn some(a: &[MalVal]) -> MalRet {
+  let mut s = a[1].clone();
+  while let Some((x, rest)) = s.first_rest()? {
+    let res = a[0].apply(vec![x])?;
+    if truthy(&res) {
+      return Ok(res);
+    }
+    s = rest;
+  }
+  Ok(Nil)
+}
+
+fn every_q(a: &[MalVal]) -> MalRet {
+  let mut s = a[1].clone();
+  while let Some((x, rest)) = s.first_rest()? {
+    if !truthy(&a[0].apply(vec![x])?) {
+      return Ok(Bool(false));
+    }
+    s = rest;
+  }
+  Ok(Bool(true))
+}
+
+// the rank of each kind of value in the total order of compare
+fn type_rank(v: &MalVal) -> u8 {
+  match v {
+    Nil => 0,
+    Bool(_) => 1,
+    Int(_) | Float(_) | MalVal::BigInt(_) | MalVal::Ratio(_) => 2,
+    Str(_) => 3,
+    Keyword(_) => 4,
+    Sym(_) => 5,
+    List(..) | Vector(..) | LazySeq(..) => 6,
+    Hash(..) => 7,
+    Set(..) => 8,
+    MalVal::Regex(_) => 9,
+    MalVal::Tagged(..) => 10,
+    Func(..) | MalFunc{..} => 11,
+    Atom(_) => 12,
+    MalVal::Reduced(_) => 13,
+  }
+}
+
+// a total order over values: values of different kinds are ordered by
+// kind, numbers by value with NaN after all others, sequences item by
+// item and maps and sets by their sorted items; functions and atoms,
+// which only equal themselves, are ordered by address
+pub fn compare(a: &MalVal, b: &MalVal) -> Ordering {
+  fn sorted(v: &MalVal) -> Vec<MalVal> {
+    let mut items = v.seq_vec().unwrap_or_default();
+    items.sort_by(compare);
+    items
+  }
+  fn addr<T: ?Sized>(p: &Rc<T>) -> usize {
+    Rc::as_ptr(p) as *const u8 as usize
+  }
+  match (a, b) {
+    (Bool(x), Bool(y)) => x.cmp(y),
+    (Str(x), Str(y)) | (Sym(x), Sym(y)) => x.cmp(y),
+    (Keyword(x), Keyword(y)) => x.cmp(y),
+    (MalVal::Regex(x), MalVal::Regex(y)) => x.re.as_str().cmp(y.re.as_str()),
+    (MalVal::Tagged(t, x), MalVal::Tagged(u, y)) => t.cmp(u).then_with(|| compare(x, y)),
+    (Func(x,_), Func(y,_)) => addr(x).cmp(&addr(y)),
+    (MalFunc{ast: x, ..}, MalFunc{ast: y, ..}) => addr(x).cmp(&addr(y)),
+    (Func(..), MalFunc{..}) => Ordering::Less,
+    (MalFunc{..}, Func(..)) => Ordering::Greater,
+    (Atom(x), Atom(y)) => addr(x).cmp(&addr(y)),
+    (MalVal::Reduced(x), MalVal::Reduced(y)) => compare(x, y),
+    _ => match type_rank(a).cmp(&type_rank(b)) {
+      Ordering::Equal => match type_rank(a) {
+        2 => {
+          let nan = |v: &MalVal| matches!(v, Float(f) if f.is_nan());
+          numeric::num_cmp(a, b).ok().flatten().unwrap_or_else(|| nan(a).cmp(&nan(b)))
+        },
+        6 => {
+          let (x, y) = (a.seq_vec().unwrap_or_default(), b.seq_vec().unwrap_or_default());
+          x.iter().zip(y.iter()).map(|(x, y)| compare(x, y))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(x.len().cmp(&y.len()))
+        },
+        _ => {
+          let (x, y) = (sorted(a), sorted(b));
+          x.len().cmp(&y.len()).then_with(|| {
+            x.iter().zip(y.iter()).map(|(x, y)| compare(x, y))
+              .find(|o| *o != Ordering::Equal)
+              .unwrap_or(Ordering::Equal)
+          })
+        },
+      },
+      o => o,
+    },
+  }
+}
+
+fn compare_fn(a: &[MalVal]) -> MalRet {
+  Ok(Int(compare(&a[0], &a[1]) as i64))
+}
+
+// orders x and y by the comparator cmp, which returns a number, or is a
+// predicate such as < that is true when x comes first
+fn compare_with(cmp: &MalVal, x: &MalVal, y: &MalVal) -> Result<Ordering,MalErr> {
+  match cmp.apply(vec![x.clone(), y.clone()])? {
+    Bool(true) => Ok(Ordering::Less),
+    Bool(false) | Nil => match truthy(&cmp.apply(vec![y.clone(), x.clone()])?) {
+      true  => Ok(Ordering::Greater),
+      false => Ok(Ordering::Equal),
+    },
+    n => match numeric::num_cmp(&n, &Int(0))? {
+      Some(o) => Ok(o),
+      None => Err(MalErr::new(ErrKind::Type,
+                              &format!("comparator returned {}", n.pr_str(true)))),
+    },
+  }
+}
+
+// a stable sort of items by their keys, with compare or with cmp
+fn sort_items(items: Vec<MalVal>, keys: Vec<MalVal>, cmp: Option<&MalVal>) -> MalRet {
+  let mut pairs: Vec<(MalVal,MalVal)> = keys.into_iter().zip(items).collect();
+  let mut err = None;
+  pairs.sort_by(|(x, _), (y, _)| match cmp {
+    _ if err.is_some() => Ordering::Equal,
+    None => compare(x, y),
+    Some(cmp) => compare_with(cmp, x, y).unwrap_or_else(|e| {
+      err = Some(e);
+      Ordering::Equal
+    }),
+  });
+  match err {
+    Some(e) => Err(e),
+    None    => Ok(list!(pairs.into_iter().map(|(_, x)| x).collect::<Vec<MalVal>>())),
+  }
+}
+
+// (sort coll) or (sort cmp coll)
+fn sort(a: &[MalVal]) -> MalRet {
+  let items = a[a.len()-1].seq_vec()?;
+  let cmp = if a.len() == 2 { Some(&a[0]) } else { None };
+  sort_items(items.clone(), items, cmp)
+}
+
+// (sort-by keyfn coll) or (sort-by keyfn cmp coll)
+fn sort_by(a: &[MalVal]) -> MalRet {
+  let items = a[a.len()-1].seq_vec()?;
+  let keys = items.iter().map(|x| a[0].apply(vec![x.clone()])).collect::<Result<_,_>>()?;
+  let cmp = if a.len() == 3 { Some(&a[1]) } else { None };
+  sort_items(items, keys, cmp)
+}
+
+// a map of (f x) to a vector of the items x with that result, in order
+fn group_by(a: &[MalVal]) -> MalRet {
+  let mut groups = MalMap::default();
+  for x in a[1].seq_vec()? {
+    let k = a[0].apply(vec![x.clone()])?;
+    match groups.get_mut(&k) {
+      Some(Vector(v,_)) => Rc::make_mut(v).push_back(x),
+      _ => { groups.insert(k, vector!(vec![x])); },
+    }
+  }
+  Ok(Hash(Rc::new(groups), Rc::new(Nil)))
+}
+
+fn frequencies(a: &[MalVal]) -> MalRet {
+  let mut counts = MalMap::default();
+  for x in a[0].seq_vec()? {
+    let n = match counts.get(&x) {
+      Some(Int(n)) => n + 1,
+      _ => 1,
+    };
+    counts.insert(x, Int(n));
+  }
+  Ok(Hash(Rc::new(counts), Rc::new(Nil)))
+}
+
+// (partition n coll), (partition n step coll) or (partition n step pad
+// coll): lists of n items, starting step items apart; a final short list
+// is dropped, unless pad is given to fill it out
+fn partition(a: &[MalVal]) -> MalRet {
+  fn partition_seq(n: usize, step: usize, pad: Option<MalVal>, seq: MalVal) -> MalVal {
+    lazy_seq(move || {
+      let (mut part, mut s) = (vec![], seq.clone());
+      while part.len() < n {
+        match s.first_rest()? {
+          Some((x, rest)) => { part.push(x); s = rest; },
+          None => break,
+        }
+      }
+      if part.is_empty() {
+        return Ok(Nil);
+      }
+      if part.len() < n {
+        match pad {
+          Some(ref pad) => {
+            part.extend(pad.seq_vec()?.into_iter().take(n - part.len()));
+            return Ok(lazy_cons(list!(part), Nil));
+          },
+          None => return Ok(Nil),
+        }
+      }
+      let mut rest = seq.clone();
+      for _ in 0..step {
+        rest = match rest.first_rest()? {
+          Some((_, r)) => r,
+          None => Nil,
+        };
+      }
+      Ok(lazy_cons(list!(part), partition_seq(n, step, pad.clone(), rest)))
+    })
+  }
+  let n = count_arg(&a[0], "partition")?;
+  let step = if a.len() > 2 { count_arg(&a[1], "partition")? } else { n };
+  if n <= 0 || step <= 0 {
+    return error_kind(ErrKind::Type, "partition: n and step must be positive");
+  }
+  let pad = if a.len() > 3 { Some(a[2].clone()) } else { None };
+  Ok(partition_seq(n as usize, step as usize, pad, a[a.len()-1].clone()))
+}
+
+// the first item of each coll, then the second of each, and so on until
+// any coll runs out
+fn interleave(a: &[MalVal]) -> MalRet {
+  fn interleave_seq(seqs: Vec<MalVal>) -> MalVal {
+    lazy_seq(move || {
+      let mut firsts = vec![];
+      let mut rests = vec![];
+      for s in seqs.iter() {
+        match s.first_rest()? {
+          Some((x, rest)) => { firsts.push(x); rests.push(rest); },
+          None => return Ok(Nil),
+        }
+      }
+      let tail = if seqs.is_empty() { Nil } else { interleave_seq(rests) };
+      Ok(firsts.into_iter().rev().fold(tail, |s, x| lazy_cons(x, s)))
+    })
+  }
+  Ok(interleave_seq(a.to_vec()))
+}
+
+// the items without duplicates, each where it first appears
+fn distinct(a: &[MalVal]) -> MalRet {
+  fn distinct_seq(seen: MalSet, seq: MalVal) -> MalVal {
+    lazy_seq(move || {
+      let mut s = seq.clone();
+      while let Some((x, rest)) = s.first_rest()? {
+        if !seen.contains(&x) {
+          let seen = seen.update(x.clone());
+          return Ok(lazy_cons(x, distinct_seq(seen, rest)));
+        }
+        s = rest;
+      }
+      Ok(Nil)
+    })
+  }
+  Ok(distinct_seq(MalSet::default(), a[0].clone()))
+}
+
+// a map of each key to the value at the same place, up to the shorter
+fn zipmap(a: &[MalVal]) -> MalRet {
+  let mut hm = MalMap::default();
+  let (mut ks, mut vs) = (a[0].clone(), a[1].clone());
+  while let (Some((k, kr)), Some((v, vr))) = (ks.first_rest()?, vs.first_rest()?) {
+    hm.insert(k, v);
+    ks = kr;
+    vs = vr;
+  }
+  Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+}
+
+// conjoins every item of from onto to; items of a map are [k v] pairs
+fn into(a: &[MalVal]) -> MalRet {
+  let items = a[1].seq_vec()?;
+  match a[0] {
+    Hash(ref hm,_) => {
+      let mut hm = (**hm).clone();
+      for kv in items {
+        match kv {
+          List(ref l,_) if l.len() == 2 => {
+            hm.insert(l[0].clone(), l[1].clone());
+          },
+          Vector(ref v,_) if v.len() == 2 => {
+            hm.insert(v[0].clone(), v[1].clone());
+          },
+          Hash(ref other,_) => hm.extend(other.iter().map(|(k, v)| (k.clone(), v.clone()))),
+          _ => return error_kind(ErrKind::Type,
+                                 &format!("into: expected a [key value] pair, got {}",
+                                          kv.pr_str(true))),
+        }
+      }
+      Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+    },
+    _ => {
+      let mut args = vec![match a[0] { Nil => list![], _ => a[0].clone() }];
+      args.extend(items);
+      conj(&args)
+    },
   }
 }
 
-fn conj(a: MalArgs) -> MalRet {
+fn truthy(v: &MalVal) -> bool {
+  !matches!(v, Nil | Bool(false))
+}
+
+fn conj(a: &[MalVal]) -> MalRet {
   match a[0] {
+    LazySeq(..) => Ok(a[1..].iter().fold(a[0].clone(), |s, x| lazy_cons(x.clone(), s))),
     List(ref v,_) => {
-      let sl = a[1..].iter().rev().map(|a|{a.clone()}).collect::<Vec<MalVal>>();
-      Ok(list!([&sl[..],v].concat()))
+      Ok(list!(a[1..].iter().fold((**v).clone(), |l, mv| l.cons(mv.clone()))))
     },
-    Vector(ref v,_) => Ok(vector!([v,&a[1..]].concat())),
-    _ => error("conj: called with non-seq"),
+    Vector(ref v,_) => {
+      let mut new_v = (**v).clone();
+      new_v.extend(a[1..].iter().cloned());
+      Ok(vector!(new_v))
+    },
+    Set(ref hs,_) => {
+      let mut new_hs = (**hs).clone();
+      new_hs.extend(a[1..].iter().cloned());
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
+    },
+    _ => error_kind(ErrKind::Type, "conj: called with non-seq"),
   }
 }
 
-fn seq(a: MalArgs) -> MalRet {
+fn disj(a: &[MalVal]) -> MalRet {
   match a[0] {
-    List(ref v,_) | Vector(ref v,_) if v.len() == 0 => Ok(Nil),
-    List(ref v,_) | Vector(ref v,_) => Ok(list!(v.to_vec())),
-    Str(ref s) if s.len() == 0 => Ok(Nil),
-    Str(ref s) if !a[0].keyword_q() => {
-      Ok(list!(s.chars().map(|c|{Str(c.to_string())}).collect()))
+    Set(ref hs,_) => {
+      let mut new_hs = (**hs).clone();
+      for mv in a[1..].iter() {
+        new_hs.remove(mv);
+      }
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
     },
     Nil => Ok(Nil),
-    _ => error("seq: called with non-seq"),
+    _ => error_kind(ErrKind::Type, "disj: called with non-set"),
+  }
+}
+
+fn set(a: &[MalVal]) -> MalRet {
+  match seq(a)? {
+    List(ref v,_) => Ok(hash_set(v.iter().cloned().collect())),
+    _ => Ok(hash_set(vec![])),
+  }
+}
+
+fn seq(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    List(ref l,_) if l.is_empty() => Ok(Nil),
+    List(ref l,_) => Ok(list!((**l).clone())),
+    Vector(ref v,_) if v.is_empty() => Ok(Nil),
+    Vector(ref v,_) => Ok(list!((**v).clone())),
+    Hash(ref hm,_) if hm.is_empty() => Ok(Nil),
+    Hash(ref hm,_) => {
+      Ok(list!(hm.iter().map(|(k,v)| vector![k.clone(), v.clone()]).collect::<MalList>()))
+    },
+    Set(ref hs,_) if hs.is_empty() => Ok(Nil),
+    Set(ref hs,_) => Ok(list!(hs.iter().cloned().collect::<MalList>())),
+    LazySeq(..) => Ok(if a[0].first_rest()?.is_some() { a[0].clone() } else { Nil }),
+    Str(ref s) if s.is_empty() => Ok(Nil),
+    Str(ref s) => {
+      Ok(list!(s.chars().map(|c|{Str(c.to_string())}).collect::<MalList>()))
+    },
+    Nil => Ok(Nil),
+    _ => error_kind(ErrKind::Type, "seq: called with non-seq"),
   }
 }
 
 pub fn ns() -> Vec<(&'static str, MalVal)> {
+  let rl = RefCell::new(Editor::<()>::new());
   vec![
-    ("=",        func(|a|{Ok(Bool(a[0] == a[1]))})),
-    ("throw",    func(|a|{Err(ErrMalVal(a[0].clone()))})),
-
-    ("nil?",     func(fn_is_type!(Nil))),
-    ("true?",    func(fn_is_type!(Bool(true)))),
-    ("false?",   func(fn_is_type!(Bool(false)))),
-    ("symbol",   func(symbol)),
-    ("symbol?",  func(fn_is_type!(Sym(_)))),
-    ("string?",  func(fn_is_type!(Str(ref s) if !s.starts_with("\u{29e}")))),
-    ("keyword",  func(|a|{a[0].keyword()})),
-    ("keyword?", func(fn_is_type!(Str(ref s) if s.starts_with("\u{29e}")))),
-    ("number?",  func(fn_is_type!(Int(_)))),
-    ("fn?",      func(fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_)))),
-    ("macro?",   func(fn_is_type!(MalFunc{is_macro,..} if is_macro))),
-
-    ("pr-str",   func(|a|Ok(Str(pr_seq(&a, true, "", "", " "))))),
-    ("str",      func(|a|Ok(Str(pr_seq(&a, false, "", "", ""))))),
-    ("prn",      func(|a|{println!("{}", pr_seq(&a, true, "", "", " ")); Ok(Nil)})),
-    ("println",  func(|a|{println!("{}", pr_seq(&a, false, "", "", " ")); Ok(Nil)})),
-    ("read-string", func(fn_str!(|s|{read_str(s)}))),
-    ("readline", func(readline)),
-    ("slurp",    func(fn_str!(|f|{slurp(f)}))),
-
-    ("<",  func(fn_t_int_int!(Bool,|i,j|{i<j}))),
-    ("<=", func(fn_t_int_int!(Bool,|i,j|{i<=j}))),
-    (">",  func(fn_t_int_int!(Bool,|i,j|{i>j}))),
-    (">=", func(fn_t_int_int!(Bool,|i,j|{i>=j}))),
-    ("+",  func(fn_t_int_int!(Int,|i,j|{i+j}))),
-    ("-",  func(fn_t_int_int!(Int,|i,j|{i-j}))),
-    ("*",  func(fn_t_int_int!(Int,|i,j|{i*j}))),
-    ("/",  func(fn_t_int_int!(Int,|i,j|{i/j}))),
-    ("time-ms", func(time_ms)),
-
-    ("sequential?", func(fn_is_type!(List(_,_),Vector(_,_)))),
-    ("list",     func(|a|{Ok(list!(a))})),
-    ("list?",    func(fn_is_type!(List(_,_)))),
-    ("vector",   func(|a|{Ok(vector!(a))})),
-    ("vector?",  func(fn_is_type!(Vector(_,_)))),
-    ("hash-map", func(|a|{hash_map(a)})),
-    ("map?",     func(fn_is_type!(Hash(_,_)))),
-    ("assoc",    func(assoc)),
-    ("dissoc",   func(dissoc)),
-    ("get",      func(get)),
-    ("contains?", func(contains_q)),
-    ("keys",     func(keys)),
-    ("vals",     func(vals)),
-
-    ("cons",   func(cons)),
-    ("concat", func(concat)),
-    ("empty?", func(|a|{a[0].empty_q()})),
-    ("nth",    func(nth)),
-    ("first",  func(first)),
-    ("rest",   func(rest)),
-    ("count",  func(|a|{a[0].count()})),
-    ("apply",  func(apply)),
-    ("map",    func(map)),
-
-    ("conj",   func(conj)),
-    ("seq",    func(seq)),
-
-    ("meta",   func(|a|{a[0].get_meta()})),
-    ("with-meta", func(|a|{a[0].clone().with_meta(&a[1])})),
-    ("atom",   func(|a|{Ok(atom(&a[0]))})),
-    ("atom?",  func(fn_is_type!(Atom(_)))),
-    ("deref",  func(|a|{a[0].deref()})),
-    ("reset!", func(|a|{a[0].reset_bang(&a[1])})),
-    ("swap!",  func(|a|{a[0].swap_bang(&a[1..].to_vec())})),
+    native!("=",        1, _, |a|{Ok(Bool(a.windows(2).all(|w| w[0] == w[1])))}),
+    native!("not=",     1, _, |a|{Ok(Bool(!a.windows(2).all(|w| w[0] == w[1])))}),
+    native!("throw",    1, 1, |a|{Err(MalErr::throw(a[0].clone()))}),
+
+    native!("nil?",     1, 1, fn_is_type!(Nil)),
+    native!("true?",    1, 1, fn_is_type!(Bool(true))),
+    native!("false?",   1, 1, fn_is_type!(Bool(false))),
+    native!("symbol",   1, 1, symbol),
+    native!("symbol?",  1, 1, fn_is_type!(Sym(_))),
+    native!("string?",  1, 1, fn_is_type!(Str(_))),
+    native!("keyword",  1, 2, keyword),
+    native!("keyword?", 1, 1, fn_is_type!(Keyword(_))),
+    native!("name",     1, 1, |a|{a[0].name()}),
+    native!("namespace", 1, 1, |a|{a[0].namespace()}),
+    native!("number?",  1, 1, fn_is_type!(Int(_),Float(_),MalVal::BigInt(_),MalVal::Ratio(_))),
+    native!("int?",     1, 1, fn_is_type!(Int(_))),
+    native!("integer?", 1, 1, fn_is_type!(Int(_),MalVal::BigInt(_))),
+    native!("ratio?",   1, 1, fn_is_type!(MalVal::Ratio(_))),
+    native!("rational?", 1, 1, fn_is_type!(Int(_),MalVal::BigInt(_),MalVal::Ratio(_))),
+    native!("float?",   1, 1, fn_is_type!(Float(_))),
+    native!("fn?",      1, 1, fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_))),
+    native!("macro?",   1, 1, fn_is_type!(MalFunc{is_macro,..} if is_macro)),
+
+    native!("pr-str",   0, _, |a|Ok(Str(pr_seq(a, true, "", "", " ")?))),
+    native!("str",      0, _, |a|Ok(Str(pr_seq(a, false, "", "", "")?))),
+    native!("prn",      0, _, |a|{println!("{}", pr_seq(a, true, "", "", " ")?); Ok(Nil)}),
+    native!("println",  0, _, |a|{println!("{}", pr_seq(a, false, "", "", " ")?); Ok(Nil)}),
+    native!("read-string", 1, 1, fn_str!(|s|{read_str(s)})),
+    native!("readline", 1, 1, move |a|{readline(&rl, a)}),
+    native!("slurp",    1, 1, fn_str!(|f|{slurp(f)})),
+    native!("read-file", 1, 1, fn_str!(|f|{read_forms(f)})),
+    native!("spit",     2, 3, files::spit),
+    native!("read-lines", 1, 1, files::read_lines),
+    native!("file-exists?", 1, 1, files::file_exists_q),
+    native!("dir?",     1, 1, files::dir_q),
+    native!("file-size", 1, 1, files::file_size),
+    native!("list-dir", 1, 1, files::list_dir),
+    native!("mkdir",    1, 1, files::mkdir),
+    native!("delete-file", 1, 1, files::delete_file),
+    native!("rename-file", 2, 2, files::rename_file),
+    native!("path-join", 1, _, files::path_join),
+    native!("basename", 1, 1, files::basename),
+    native!("dirname",  1, 1, files::dirname),
+    native!("abs-path", 1, 1, files::abs_path),
+    native!("json-parse", 1, 2, json::json_parse),
+    native!("json-str", 1, 2, json::json_str),
+    native!("edn/read-string", 1, 2, edn_read_string),
+    native!("edn/pr-str", 0, _, edn_pr_str),
+    native!("tagged-literal", 2, 2, tagged_literal),
+    native!("tagged-literal?", 1, 1, fn_is_type!(MalVal::Tagged(_,_))),
+
+    native!("regex?",     1, 1, fn_is_type!(MalVal::Regex(_))),
+    native!("re-pattern", 1, 1, regexp::re_pattern),
+    native!("re-find",    2, 2, regexp::re_find),
+    native!("re-matches", 2, 2, regexp::re_matches),
+    native!("re-seq",     2, 2, regexp::re_seq),
+    native!("re-groups",  2, 2, regexp::re_groups),
+    native!("replace",    3, 3, regexp::replace),
+
+    native!("subs",       2, 3, strings::subs),
+    native!("split",      2, 3, strings::split),
+    native!("split-lines", 1, 1, strings::split_lines),
+    native!("join",       1, 2, strings::join),
+    native!("trim",       1, 1, strings::trim),
+    native!("triml",      1, 1, strings::triml),
+    native!("trimr",      1, 1, strings::trimr),
+    native!("upper-case", 1, 1, strings::upper_case),
+    native!("lower-case", 1, 1, strings::lower_case),
+    native!("starts-with?", 2, 2, strings::starts_with_q),
+    native!("ends-with?", 2, 2, strings::ends_with_q),
+    native!("includes?",  2, 2, strings::includes_q),
+    native!("index-of",   2, 3, strings::index_of),
+    native!("blank?",     1, 1, strings::blank_q),
+    native!("byte-count", 1, 1, strings::byte_count),
+    native!("byte-subs",  2, 3, strings::byte_subs),
+    native!("byte-at",    2, 2, strings::byte_at),
+    native!("byte-index-of", 2, 3, strings::byte_index_of),
+    native!("reverse",    1, 1, strings::reverse),
+    native!("format",     1, _, strings::format),
+
+    native!("<",  1, _, numeric::lt),
+    native!("<=", 1, _, numeric::lte),
+    native!(">",  1, _, numeric::gt),
+    native!(">=", 1, _, numeric::gte),
+    native!("+",  0, _, numeric::add),
+    native!("-",  1, _, numeric::sub),
+    native!("*",  0, _, numeric::mul),
+    native!("/",  1, _, numeric::div),
+    native!("+'", 0, _, numeric::add_p),
+    native!("-'", 1, _, numeric::sub_p),
+    native!("*'", 0, _, numeric::mul_p),
+    native!("quot",   2, 2, numeric::quot),
+    native!("rem",    2, 2, numeric::rem),
+    native!("mod",    2, 2, numeric::modulo),
+    native!("double", 1, 1, numeric::double),
+    native!("int",    1, 1, numeric::int),
+    native!("floor",  1, 1, numeric::floor),
+    native!("ceil",   1, 1, numeric::ceil),
+    native!("round",  1, 1, numeric::round),
+    native!("rationalize", 1, 1, numeric::rationalize),
+    native!("numerator",   1, 1, numeric::numerator),
+    native!("denominator", 1, 1, numeric::denominator),
+    native!("time-ms", 0, 0, time_ms),
+    native!("sh",      1, _, sh),
+    native!("getenv",  0, 1, getenv),
+    native!("setenv",  2, 2, setenv),
+    native!("exit",    0, 1, exit),
+    native!("parse-args", 1, 1, parse_args),
+
+    native!("sequential?", 1, 1, fn_is_type!(List(_,_),Vector(_,_),LazySeq(_,_))),
+    native!("seq?",     1, 1, fn_is_type!(List(_,_),LazySeq(_,_))),
+    native!("list",     0, _, |a|{Ok(list!(a.to_vec()))}),
+    native!("list?",    1, 1, fn_is_type!(List(_,_))),
+    native!("vector",   0, _, |a|{Ok(vector!(a))}),
+    native!("vector?",  1, 1, fn_is_type!(Vector(_,_))),
+    native!("hash-map", 0, _, |a|{hash_map(a.to_vec())}),
+    native!("map?",     1, 1, fn_is_type!(Hash(_,_))),
+    native!("hash-set", 0, _, |a|{Ok(hash_set(a.to_vec()))}),
+    native!("set",      1, 1, set),
+    native!("set?",     1, 1, fn_is_type!(Set(_,_))),
+    native!("disj",     1, _, disj),
+    native!("assoc",    1, _, assoc),
+    native!("dissoc",   1, _, dissoc),
+    native!("get",      2, 2, get),
+    native!("contains?", 2, 2, contains_q),
+    native!("keys",     1, 1, keys),
+    native!("vals",     1, 1, vals),
+
+    native!("cons",   2, 2, cons),
+    native!("concat", 0, _, concat),
+    native!("empty?", 1, 1, |a|{a[0].empty_q()}),
+    native!("nth",    2, 2, nth),
+    native!("first",  1, 1, first),
+    native!("rest",   1, 1, rest),
+    native!("count",  1, 1, |a|{a[0].count()}),
+    native!("apply",  2, _, apply),
+    native!("map",    2, _, map),
+
+    native!("lazy-seq*",  1, 1, lazy_seq_fn),
+    native!("doall",      1, 1, |a|{a[0].seq_vec()?; Ok(a[0].clone())}),
+    native!("iterate",    2, 2, iterate),
+    native!("range",      0, 3, range),
+    native!("repeat",     1, 2, repeat),
+    native!("take",       2, 2, take),
+    native!("drop",       2, 2, drop),
+    native!("take-while", 2, 2, take_while),
+    native!("filter",     2, 2, filter),
+    native!("remove",     2, 2, remove),
+    native!("keep",       2, 2, keep),
+    native!("partition",  2, 4, partition),
+    native!("interleave", 0, _, interleave),
+    native!("distinct",   1, 1, distinct),
+
+    native!("reduce",     2, 3, reduce),
+    native!("reduced",    1, 1, |a|{Ok(MalVal::Reduced(Rc::new(a[0].clone())))}),
+    native!("reduced?",   1, 1, fn_is_type!(MalVal::Reduced(_))),
+    native!("some",       2, 2, some),
+    native!("every?",     2, 2, every_q),
+    native!("compare",    2, 2, compare_fn),
+    native!("sort",       1, 2, sort),
+    native!("sort-by",    2, 3, sort_by),
+    native!("group-by",   2, 2, group_by),
+    native!("frequencies", 1, 1, frequencies),
+    native!("zipmap",     2, 2, zipmap),
+    native!("into",       2, 2, into),
+
+    native!("conj",   1, _, conj),
+    native!("seq",    1, 1, seq),
+
+    native!("meta",   1, 1, |a|{a[0].get_meta()}),
+    native!("with-meta", 2, 2, |a|{a[0].clone().with_meta(&a[1])}),
+    native!("atom",   1, 1, |a|{Ok(atom(&a[0]))}),
+    native!("atom?",  1, 1, fn_is_type!(Atom(_))),
+    native!("deref",  1, 1, |a|{a[0].deref()}),
+    native!("reset!", 2, 2, |a|{a[0].reset_bang(&a[1])}),
+    native!("swap!",  2, _, |a|{a[0].swap_bang(&a[1..].to_vec())}),
   ]
 }
 
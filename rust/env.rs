@@ -1,11 +1,11 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use itertools::Itertools;
 //use std::collections::HashMap;
 use fnv::FnvHashMap;
 
-use types::{MalVal,MalRet,MalErr,error};
-use types::MalVal::{Nil,Sym,List,Vector};
-use types::MalErr::{ErrString};
+use types::{MalVal,MalMap,MalRet,MalErr,ErrKind,error_kind,arity_error,func,keyword};
+use types::MalVal::{Nil,Str,Keyword,Sym,List,Vector,Hash,LazySeq};
 
 #[derive(Debug)]
 pub struct EnvStruct {
@@ -19,29 +19,171 @@ pub type Env = Rc<EnvStruct>;
 // a deftype (i.e. Env)
 
 pub fn env_new(outer: Option<Env>) -> Env {
-  Rc::new(EnvStruct{data: RefCell::new(FnvHashMap::default()), outer: outer})
+  Rc::new(EnvStruct{data: RefCell::new(FnvHashMap::default()), outer})
 }
 
 // TODO: mbinds and exprs as & types
-pub fn env_bind(outer: Option<Env>, mbinds: MalVal,
-                exprs: Vec<MalVal>) -> Result<Env,MalErr> {
+// each param may be a destructuring pattern, as for env_destructure
+pub fn env_bind(outer: Option<Env>, mbinds: MalVal, exprs: Vec<MalVal>,
+                eval: fn(MalVal, Env) -> MalRet) -> Result<Env,MalErr> {
   let env = env_new(outer);
-  match mbinds {
-    List(binds,_) | Vector(binds,_) => {
-      for (i, b) in binds.iter().enumerate() {
-        match b {
-          Sym(s) if s == "&" => {
-            env_set(&env, binds[i+1].clone(), list!(exprs[i..].to_vec()))?;
-            break;
+  let (mut binds, variadic) = match (mbinds.items(), mbinds.items()) {
+    (Some(binds), Some(mut bs)) => (binds, bs.position(|b| matches!(b, Sym(s) if s == "&"))),
+    _ => return Err(MalErr::new(ErrKind::Type, "env_bind binds not List/Vector")),
+  };
+  let ok = match variadic {
+    Some(i) if i + 2 != binds.len() => {
+      return Err(MalErr::new(ErrKind::Type,
+                             "'&' must be followed by exactly one param"));
+    },
+    Some(i) => exprs.len() >= i,
+    None    => exprs.len() == binds.len(),
+  };
+  if !ok {
+    return arity_error(exprs.len(),
+                       &format!("fn* with params {}", mbinds.pr_str(true)));
+  }
+  // the params before any '&' take one arg each, and the one after it
+  // takes a list of the rest
+  let mut exprs = exprs;
+  let rest = variadic.map(|i| exprs.split_off(i));
+  for (b, e) in binds.by_ref().take(exprs.len()).zip(exprs) {
+    env_destructure(&env, b, e, eval)?;
+  }
+  if let (Some(rest), Some(p)) = (rest, binds.nth(1)) {
+    env_destructure(&env, p, list!(rest), eval)?;
+  }
+  Ok(env)
+}
+
+fn bind_error(msg: &str) -> MalErr {
+  MalErr::new(ErrKind::Type, msg)
+}
+
+// binds the symbols of a binding form to the parts of val, as Clojure
+// destructures: a symbol takes the whole value; a vector or list takes
+// successive items, then the rest after '&', then the whole after :as;
+// a map takes the value of the key of each {pattern key} entry, of the
+// keywords, strings or symbols named by :keys, :strs or :syms, with
+// defaults from :or, evaluated in env, for missing keys, and the whole
+// after :as. Patterns nest, and a missing item or key binds nil.
+pub fn env_destructure(env: &Env, pat: &MalVal, val: MalVal,
+                       eval: fn(MalVal, Env) -> MalRet) -> Result<(),MalErr> {
+  match pat {
+    Sym(s) if s == "&" => Err(bind_error("'&' must be followed by exactly one param")),
+    Sym(_) => env_set(env, pat.clone(), val).map(|_| ()),
+    List(..) | Vector(..) => {
+      let ps: Vec<&MalVal> = pat.items().map_or(vec![], |ps| ps.collect());
+      let mut s = match val {
+        Nil | List(..) | Vector(..) | LazySeq(..) | Str(_) => val.clone(),
+        _ => return Err(bind_error(&format!("cannot destructure {} as a sequence",
+                                            val.pr_str(true)))),
+      };
+      let (mut i, mut rested) = (0, false);
+      while i < ps.len() {
+        match ps[i] {
+          Sym(a) if a == "&" => {
+            let rest = match ps.get(i + 1).copied() {
+              Some(Keyword(k)) if &**k == "as" => None,
+              rest => rest,
+            };
+            let rest = rest.ok_or_else(|| bind_error("'&' must be followed by exactly one param"))?;
+            let rv = match s.first_rest()? {
+              None => Nil,
+              Some(_) => match s {
+                List(..) | LazySeq(..) => s.clone(),
+                _ => list!(s.seq_vec()?),
+              },
+            };
+            env_destructure(env, rest, rv, eval)?;
+            rested = true;
+            i += 2;
           },
-          _ => {
-            env_set(&env, b.clone(), exprs[i].clone())?;
+          Keyword(k) if &**k == "as" => {
+            match (ps.get(i + 1).copied(), i + 2 == ps.len()) {
+              (Some(a @ Sym(_)), true) => env_set(env, a.clone(), val.clone())?,
+              _ => return Err(bind_error(":as must be followed by a symbol at the end of the binding form")),
+            };
+            i += 2;
           },
+          p => {
+            // only :as may follow the rest
+            if rested {
+              return Err(bind_error("'&' must be followed by exactly one param"));
+            }
+            let (x, rest) = s.first_rest()?.unwrap_or((Nil, Nil));
+            env_destructure(env, p, x, eval)?;
+            s = rest;
+            i += 1;
+          },
+        }
+      }
+      Ok(())
+    },
+    Hash(ps,_) => {
+      let hm: Rc<MalMap> = match val {
+        Nil => Rc::new(MalMap::default()),
+        Hash(ref hm,_) => hm.clone(),
+        // the rest args of a fn*, as in (fn* [& {:keys [a]}] a)
+        List(..) | LazySeq(..) => {
+          let kvs = val.seq_vec()?;
+          if !kvs.len().is_multiple_of(2) {
+            return Err(bind_error(&format!("cannot destructure {} as a map, it has an odd number of items",
+                                           val.pr_str(true))));
+          }
+          Rc::new(kvs.into_iter().tuples().collect())
+        },
+        _ => return Err(bind_error(&format!("cannot destructure {} as a map",
+                                            val.pr_str(true)))),
+      };
+      let defaults = match ps.get(&keyword("or")) {
+        None => None,
+        Some(Hash(d,_)) => Some(d.clone()),
+        Some(d) => return Err(bind_error(&format!(":or must be a map, got {}",
+                                                  d.pr_str(true)))),
+      };
+      let bind = |p: &MalVal, key: MalVal| -> Result<(),MalErr> {
+        let v = match (hm.get(&key), defaults.as_ref().and_then(|d| d.get(p))) {
+          (Some(v), _) => v.clone(),
+          (None, Some(e)) if matches!(p, Sym(_)) => eval(e.clone(), env.clone())?,
+          _ => Nil,
+        };
+        env_destructure(env, p, v, eval)
+      };
+      for (k, p) in ps.iter() {
+        match k {
+          Keyword(kw) if &**kw == "keys" || &**kw == "strs" || &**kw == "syms" => {
+            let names = match p.items() {
+              Some(names) => names,
+              None => return Err(bind_error(&format!(":{} must be followed by a vector of symbols", kw))),
+            };
+            for n in names {
+              let full = match n {
+                Sym(s) => s.to_string(),
+                Keyword(s) if &**kw == "keys" => s.to_string(),
+                _ => return Err(bind_error(&format!(":{} must be followed by a vector of symbols", kw))),
+              };
+              // a namespaced name binds just its name
+              let name = full.rsplit('/').next().unwrap_or(&full).to_string();
+              let key = match &**kw {
+                "keys" => keyword(&full),
+                "strs" => Str(full),
+                _      => Sym(full),
+              };
+              bind(&Sym(name), key)?;
+            }
+          },
+          Keyword(kw) if &**kw == "or" => (),
+          Keyword(kw) if &**kw == "as" => match p {
+            Sym(_) => env_set(env, p.clone(), val.clone()).map(|_| ())?,
+            _ => return Err(bind_error(":as must be followed by a symbol")),
+          },
+          _ => bind(k, p.clone())?,
         }
       }
-      Ok(env)
+      Ok(())
     },
-    _ => Err(ErrString("env_bind binds not List/Vector".to_string())),
+    _ => Err(bind_error(&format!("unsupported binding form {}", pat.pr_str(true)))),
   }
 }
 
@@ -58,12 +200,13 @@ pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
     Sym(ref s) => {
       match env_find(env, s) {
         Some(e) => Ok(e.data.borrow().get(s)
-                        .ok_or(ErrString(format!("'{}' not found", s)))?
+                        .ok_or(MalErr::new(ErrKind::Unbound,
+                                           &format!("'{}' not found", s)))?
                         .clone()),
-        _ => error(&format!("'{}' not found", s)),
+        _ => error_kind(ErrKind::Unbound, &format!("'{}' not found", s)),
       }
     },
-    _ => error("Env.get called with non-Str"),
+    _ => error_kind(ErrKind::Type, "Env.get called with non-Str"),
   }
 }
 
@@ -73,7 +216,7 @@ pub fn env_set(env: &Env, key: MalVal, val: MalVal) -> MalRet {
       env.data.borrow_mut().insert(s.to_string(), val.clone());
       Ok(val)
     },
-      _ => error("Env.set called with non-Str")
+      _ => error_kind(ErrKind::Type, "Env.set called with non-Str")
   }
 }
 
@@ -81,4 +224,11 @@ pub fn env_sets(env: &Env, key: &str, val: MalVal) {
   env.data.borrow_mut().insert(key.to_string(), val);
 }
 
+// binds name to a native function taking between min and max args (any
+// number when max is None); f may capture state of the embedding program
+pub fn env_set_fn<F>(env: &Env, name: &str, min: usize, max: Option<usize>, f: F)
+  where F: Fn(&[MalVal]) -> MalRet + 'static {
+  env_sets(env, name, func(name, min, max, f));
+}
+
 // vim: ts=2:sw=2:expandtab
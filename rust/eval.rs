@@ -0,0 +1,523 @@
+use std::rc::Rc;
+use itertools::Itertools;
+
+use types::{MalVal,MalList,MalMap,MalSet,MalArgs,MalRet,MalErr,ErrKind,Frame,error,error_kind,arity_error};
+use types::MalVal::{Nil,Bool,Keyword,Sym,List,Vector,Hash,Set,LazySeq,Func,MalFunc};
+use reader::{span_of,item_span};
+use env::{Env,env_new,env_bind,env_destructure,env_find,env_get,env_set,env_sets};
+
+fn quasiquote(ast: &MalVal) -> MalRet {
+  let v: MalList = match ast {
+    List(ref l,_) if !l.is_empty() => (**l).clone(),
+    Vector(ref v,_) if !v.is_empty() => (**v).clone().into(),
+    _ => return Ok(list![Sym("quote".to_string()), ast.clone()]),
+  };
+  let a0 = &v[0];
+  match a0 {
+    Sym(ref s) if s == "unquote" => {
+      check_form(&v, "unquote", 1, Some(1))?;
+      Ok(v[1].clone())
+    },
+    _ => {
+      let spliced = match a0.items() {
+        Some(mut a0_items) => match (a0_items.next(), a0_items.next()) {
+          (Some(Sym(ref s)), Some(x)) if s == "splice-unquote" => Some(x.clone()),
+          _ => None,
+        },
+        None => None,
+      };
+      match spliced {
+        Some(x) => {
+          Ok(list![Sym("concat".to_string()),
+                   x,
+                   quasiquote(&list!(v.rest()))?])
+        },
+        None => {
+          Ok(list![Sym("cons".to_string()),
+                   quasiquote(a0)?,
+                   quasiquote(&list!(v.rest()))?])
+        },
+      }
+    }
+  }
+}
+
+fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal,MalArgs)> {
+  match ast {
+    List(v,_) => {
+      match v.front() {
+        Some(a0 @ Sym(ref s)) => {
+          match env_find(env, s) {
+            Some(e) => {
+              match env_get(&e, a0) {
+                Ok(f @ MalFunc{is_macro: true, ..}) => {
+                  Some((f, v.iter().skip(1).cloned().collect()))
+                },
+                _ => None,
+              }
+            },
+            _ => None,
+          }
+        },
+        _ => None,
+      }
+    },
+    _ => None,
+  }
+}
+
+fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
+  let mut was_expanded = false;
+  while let Some((mf, args)) = is_macro_call(&ast, env) {
+    //println!("macroexpand 1: {:?}", ast);
+    ast = match mf.apply(args) {
+      Err(e) => return (false, Err(e)),
+      Ok(a) => a,
+    };
+    //println!("macroexpand 2: {:?}", ast);
+    was_expanded = true;
+  }
+  (was_expanded, Ok(ast))
+}
+
+// evaluates the items of a list or vector from the skip'th on, locating
+// an error at the item that raised it
+fn eval_items(form: &MalVal, skip: usize, env: &Env) -> Result<MalArgs,MalErr> {
+  let mut res = vec![];
+  if let Some(items) = form.items() {
+    for (i, a) in items.enumerate().skip(skip) {
+      res.push(eval(a.clone(), env.clone()).map_err(|e| e.at(item_span(form, i)))?);
+    }
+  }
+  Ok(res)
+}
+
+fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
+  match ast {
+    Sym(_)  => Ok(env_get(env, ast)?),
+    List(..) => Ok(list!(eval_items(ast, 0, env)?)),
+    Vector(..) => Ok(vector!(eval_items(ast, 0, env)?)),
+    Hash(hm,_) => {
+      let mut new_hm: MalMap = MalMap::default();
+      for (k,v) in hm.iter() {
+        new_hm.insert(eval(k.clone(), env.clone())?, eval(v.clone(), env.clone())?);
+      }
+      Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
+    },
+    Set(hs,_) => {
+      let mut new_hs: MalSet = MalSet::default();
+      for v in hs.iter() {
+        new_hs.insert(eval(v.clone(), env.clone())?);
+      }
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
+    },
+    _ => Ok(ast.clone()),
+  }
+}
+
+// the symbols a binding form binds, as by env_destructure
+fn bound_syms(pat: &MalVal, out: &mut Vec<String>) {
+  match pat {
+    Sym(s) if s != "&" => out.push(s.clone()),
+    List(..) | Vector(..) => {
+      if let Some(ps) = pat.items() {
+        ps.for_each(|p| bound_syms(p, out))
+      }
+    },
+    Hash(ps,_) => {
+      for (k, p) in ps.iter() {
+        match (k, p) {
+          (Keyword(kw), _) if &**kw == "keys" || &**kw == "strs" || &**kw == "syms" => {
+            if let Some(ns) = p.items() {
+              for n in ns {
+                match n {
+                  Sym(s) => out.push(s.rsplit('/').next().unwrap_or(s).to_string()),
+                  Keyword(s) => out.push(s.rsplit('/').next().unwrap_or(s).to_string()),
+                  _ => (),
+                }
+              }
+            }
+          },
+          (Keyword(kw), _) if &**kw == "or" => (),
+          (Keyword(kw), _) if &**kw == "as" => bound_syms(p, out),
+          _ => bound_syms(k, out),
+        }
+      }
+    },
+    _ => (),
+  }
+}
+
+// whether the symbol recur appears anywhere in ast; most fn* bodies
+// have none and can skip the slower check_recur
+fn mentions_recur(ast: &MalVal) -> bool {
+  match ast {
+    Sym(ref s) => s == "recur",
+    List(..) | Vector(..) => ast.items().is_some_and(|mut i| i.any(mentions_recur)),
+    Hash(ref hm,_) => hm.iter().any(|(k, v)| mentions_recur(k) || mentions_recur(v)),
+    Set(ref hs,_) => hs.iter().any(mentions_recur),
+    _ => false,
+  }
+}
+
+// checks, before a loop* runs, that each recur in ast is in tail position
+// of the innermost enclosing loop* and rebinds each of its arity
+// bindings; arity is None outside any loop*, as within a fn* body.
+// Forms are checked as written: a macro is not expanded, since that
+// would run it an extra time, so a recur within a macro call is only
+// caught when it runs. locals are the names bound within ast so far,
+// which shadow any macro of the same name.
+fn check_recur(ast: &MalVal, tail: bool, arity: Option<usize>, env: &Env,
+               locals: &[String]) -> Result<(),MalErr> {
+  let check = |x: &MalVal, tail: bool| check_recur(x, tail, arity, env, locals);
+  let l = match ast {
+    List(ref l,_) if !l.is_empty() => l.clone(),
+    List(..) => return Ok(()),
+    Vector(ref v,_) => return v.iter().try_for_each(|x| check(x, false)),
+    Hash(ref hm,_) => {
+      return hm.iter().try_for_each(|(k, v)| {
+        check(k, false)?;
+        check(v, false)
+      })
+    },
+    Set(ref hs,_) => return hs.iter().try_for_each(|x| check(x, false)),
+    _ => return Ok(()),
+  };
+  if let Sym(ref s) = l[0] {
+    if !locals.contains(s) && is_macro_call(ast, env).is_some() {
+      return Ok(());
+    }
+  }
+  // the names bound by a let*, loop* or fn* along with locals
+  let with_binds = |binds: Option<&MalVal>, step: usize| {
+    let mut names = locals.to_vec();
+    if let Some(b) = binds.and_then(|b| b.items()) {
+      b.step_by(step).for_each(|p| bound_syms(p, &mut names));
+    }
+    names
+  };
+  let check_binds = |binds: Option<&MalVal>| match binds.and_then(|b| b.items()) {
+    Some(b) => b.skip(1).step_by(2).try_for_each(|x| check(x, false)),
+    None => Ok(()),
+  };
+  match l[0] {
+    Sym(ref s) if s == "recur" => {
+      let msg = match arity {
+        None => Some("recur outside of loop*".to_string()),
+        Some(_) if !tail => Some("recur can only be used in tail position".to_string()),
+        Some(n) if l.len() - 1 != n => {
+          Some(format!("recur expected {} args to match the loop* bindings, got {}",
+                       n, l.len() - 1))
+        },
+        _ => None,
+      };
+      if let Some(msg) = msg {
+        return Err(MalErr::new(ErrKind::Error, &msg).at(span_of(ast)));
+      }
+      l.iter().skip(1).try_for_each(|x| check(x, false))
+    },
+    Sym(ref s) if s == "quote" || s == "quasiquote" => Ok(()),
+    Sym(ref s) if s == "if" => {
+      l.iter().skip(1).enumerate().try_for_each(|(i, x)| check(x, i > 0))
+    },
+    Sym(ref s) if s == "do" => {
+      l.iter().skip(1).enumerate().try_for_each(|(i, x)| check(x, i == l.len() - 2))
+    },
+    Sym(ref s) if s == "let*" => {
+      check_binds(l.get(1))?;
+      let names = with_binds(l.get(1), 2);
+      l.iter().skip(2).try_for_each(|x| check_recur(x, true, arity, env, &names))
+    },
+    Sym(ref s) if s == "loop*" => {
+      check_binds(l.get(1))?;
+      let n = l.get(1).and_then(|b| b.items()).map_or(0, |b| b.len() / 2);
+      let names = with_binds(l.get(1), 2);
+      l.iter().skip(2).try_for_each(|x| check_recur(x, true, Some(n), env, &names))
+    },
+    Sym(ref s) if s == "fn*" => {
+      let names = with_binds(l.get(1), 1);
+      l.iter().skip(2).try_for_each(|x| check_recur(x, true, None, env, &names))
+    },
+    Sym(ref s) if s == "try*" => {
+      l.iter().skip(1).try_for_each(|x| match x {
+        List(c,_) if matches!(c.front(), Some(Sym(s)) if s == "catch*") => {
+          let mut names = locals.to_vec();
+          if let Some(p) = c.get(1) {
+            bound_syms(p, &mut names);
+          }
+          c.iter().skip(2).try_for_each(|x| check_recur(x, false, arity, env, &names))
+        },
+        _ => check(x, false),
+      })
+    },
+    Sym(ref s) if s == "def!" || s == "defmacro!" => {
+      l.iter().skip(2).try_for_each(|x| check(x, false))
+    },
+    _ => l.iter().try_for_each(|x| check(x, false)),
+  }
+}
+
+// fails unless special form l has between min and max args
+fn check_form(l: &MalList, name: &str, min: usize, max: Option<usize>) -> Result<(),MalErr> {
+  let n = l.len() - 1;
+  if n < min || max.is_some_and(|max| n > max) {
+    return arity_error(n, name);
+  }
+  Ok(())
+}
+
+// the loop* that a recur in tail position goes back to: its binding
+// forms, its body and the env holding the current bindings
+struct Loop {
+  pats: Vec<MalVal>,
+  body: MalVal,
+  env: Env,
+  vals: Vec<MalVal>,
+}
+
+// What an eval was working on, for locating its errors: the innermost
+// list it evaluated and the MalFunc call form whose body it is running.
+struct Context {
+  form: MalVal,
+  call: Option<MalVal>,
+}
+
+pub fn eval(ast: MalVal, env: Env) -> MalRet {
+  let mut ctx = Context{form: Nil, call: None};
+  eval_in(ast, env, &mut ctx).map_err(|e| {
+    let mut e = e.at(span_of(&ctx.form));
+    if let Some(call) = ctx.call {
+      let name = match call {
+        List(ref l,_) if matches!(l[0], Sym(_)) => l[0].pr_str(true),
+        _ => "fn*".to_string(),
+      };
+      e.trace.push(Frame{name, span: span_of(&call)});
+    }
+    e
+  })
+}
+
+fn eval_in(mut ast: MalVal, mut env: Env, ctx: &mut Context) -> MalRet {
+  let ret: MalRet;
+  // only a recur reached without leaving this loop is in tail position
+  let mut lp: Option<Loop> = None;
+
+  'tco: loop {
+
+  ret = match ast.clone() {
+    // code built by lazy seq functions is evaluated as a list
+    LazySeq(..) => {
+      ast = list!(ast.seq_vec()?);
+      continue 'tco;
+    },
+    List(l,_) => {
+      ctx.form = ast.clone();
+      match macroexpand(ast.clone(), &env) {
+        (true, Ok(new_ast)) => {
+          ast = new_ast;
+          continue 'tco;
+        }
+        (_, Err(e)) => return Err(e),
+        _ => (),
+      }
+
+      if l.is_empty() { return Ok(ast); }
+      let a0 = &l[0];
+      match a0 {
+        Sym(ref a0sym) if a0sym == "def!" => {
+          check_form(&l, a0sym, 2, Some(2))?;
+          env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?)
+        },
+        Sym(ref a0sym) if a0sym == "let*" => {
+          check_form(&l, a0sym, 2, Some(2))?;
+          env = env_new(Some(env.clone()));
+          let (a1, a2) = (l[1].clone(), l[2].clone());
+          match a1.items() {
+            Some(binds) if binds.len() % 2 == 0 => {
+              for (b, e) in binds.tuples() {
+                env_destructure(&env, b, eval(e.clone(), env.clone())?, eval)?;
+              }
+            },
+            Some(_) => {
+              return error("let* bindings need an even number of forms");
+            },
+            None => {
+              return error("let* with non-List bindings");
+            }
+          };
+          ast = a2;
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "loop*" => {
+          check_form(&l, a0sym, 1, Some(2))?;
+          let binds: Vec<MalVal> = match l.get(1).and_then(|b| b.items()) {
+            Some(b) if b.len() % 2 == 0 => b.cloned().collect(),
+            _ => return error("loop* with non-List bindings"),
+          };
+          let body = l.get(2).cloned().unwrap_or(Nil);
+          let pats: Vec<MalVal> = binds.iter().step_by(2).cloned().collect();
+          let mut names = vec![];
+          pats.iter().for_each(|p| bound_syms(p, &mut names));
+          check_recur(&body, true, Some(pats.len()), &env, &names)?;
+          env = env_new(Some(env.clone()));
+          for (b, e) in binds.iter().tuples() {
+            env_destructure(&env, b, eval(e.clone(), env.clone())?, eval)?;
+          }
+          lp = Some(Loop{pats, body: body.clone(), env: env.clone(), vals: vec![]});
+          ast = body;
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "recur" => {
+          let lp = match lp {
+            Some(ref mut lp) => lp,
+            None => return error("recur can only be used in tail position of a loop*"),
+          };
+          if lp.pats.len() != l.len() - 1 {
+            return error(&format!("recur expected {} args to match the loop* bindings, got {}",
+                                  lp.pats.len(), l.len() - 1));
+          }
+          lp.vals.clear();
+          for x in l.iter().skip(1) {
+            lp.vals.push(eval(x.clone(), env.clone())?);
+          }
+          // the bindings are updated in place unless a closure or lazy
+          // seq has kept hold of the env
+          env = lp.env.clone();
+          if Rc::strong_count(&lp.env) > 2 {
+            lp.env = env_new(lp.env.outer.clone());
+            env = lp.env.clone();
+          }
+          for (p, v) in lp.pats.iter().zip(lp.vals.drain(..)) {
+            env_destructure(&env, p, v, eval)?;
+          }
+          ast = lp.body.clone();
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "quote" => {
+          check_form(&l, a0sym, 1, Some(1))?;
+          Ok(l[1].clone())
+        },
+        Sym(ref a0sym) if a0sym == "quasiquote" => {
+          check_form(&l, a0sym, 1, Some(1))?;
+          ast = quasiquote(&l[1])?;
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "defmacro!" => {
+          check_form(&l, a0sym, 2, Some(2))?;
+          let (a1, a2) = (l[1].clone(), l[2].clone());
+          let r = eval(a2, env.clone())?;
+          match r {
+            MalFunc{eval, ast, env, params, ..} => {
+              Ok(env_set(&env, a1.clone(),
+                 MalFunc{eval, ast: ast.clone(), env: env.clone(),
+                         params: params.clone(), is_macro: true,
+                         meta: Rc::new(Nil)})?)
+            },
+              _ => error("set_macro on non-function"),
+          }
+        },
+        Sym(ref a0sym) if a0sym == "macroexpand" => {
+          check_form(&l, a0sym, 1, Some(1))?;
+          match macroexpand(l[1].clone(), &env) {
+            (_, Ok(new_ast)) => Ok(new_ast),
+            (_, e) => return e,
+          }
+        },
+        Sym(ref a0sym) if a0sym == "try*" => {
+          check_form(&l, a0sym, 1, Some(2))?;
+          let catch = match l.get(2) {
+            None => None,
+            Some(List(c,_)) if matches!(c.front(), Some(Sym(s)) if s == "catch*") => {
+              check_form(c, "catch*", 2, Some(2))?;
+              Some(c.clone())
+            },
+            Some(_) => return error("invalid catch block"),
+          };
+          match (eval(l[1].clone(), env.clone()), catch) {
+            (Err(ref e), Some(c)) => {
+              let exc = e.value();
+              let catch_env = env_bind(Some(env.clone()),
+                                       list!(vec![c[1].clone()]),
+                                       vec![exc], eval)?;
+              // catch* binds what was thrown, as the mal tests expect;
+              // the whole error map goes in *error*, set in this catch*'s
+              // own env so nested handlers shadow rather than clobber it
+              env_sets(&catch_env, "*error*", e.to_mal());
+              eval(c[2].clone(), catch_env)
+            },
+            (res, _) => res,
+          }
+        },
+        Sym(ref a0sym) if a0sym == "do" && l.len() == 1 => Ok(Nil),
+        Sym(ref a0sym) if a0sym == "do" => {
+          for (i, x) in l.iter().enumerate().skip(1).take(l.len().saturating_sub(2)) {
+            eval(x.clone(), env.clone()).map_err(|e| e.at(item_span(&ast, i)))?;
+          }
+          ast = l.last().unwrap_or(&Nil).clone();
+          continue 'tco;
+        },
+        Sym(ref a0sym) if a0sym == "if" => {
+          check_form(&l, a0sym, 2, Some(3))?;
+          let cond = eval(l[1].clone(), env.clone())?;
+          match cond {
+            Bool(false) | Nil if l.len() >= 4 => {
+              ast = l[3].clone();
+              continue 'tco;
+            },
+            Bool(false) | Nil => Ok(Nil),
+            _ if l.len() >= 3 => {
+              ast = l[2].clone();
+              continue 'tco;
+            },
+            _ => Ok(Nil)
+          }
+        },
+        Sym(ref a0sym) if a0sym == "fn*" => {
+          check_form(&l, a0sym, 2, Some(2))?;
+          // fn* bodies have no recur target, so any recur in one, outside
+          // a nested loop*, fails here rather than when it is reached
+          if mentions_recur(&l[2]) {
+            check_recur(&ast, false, None, &env, &[])?;
+          }
+          let (a1, a2) = (l[1].clone(), l[2].clone());
+          Ok(MalFunc{eval, ast: Rc::new(a2), env,
+                     params: Rc::new(a1), is_macro: false,
+                     meta: Rc::new(Nil)})
+        },
+        Sym(ref a0sym) if a0sym == "eval" => {
+          check_form(&l, a0sym, 1, Some(1))?;
+          ast = eval(l[1].clone(), env.clone())?;
+          while let Some(ref e) = env.clone().outer {
+            env = e.clone();
+          }
+          lp = None;
+          continue 'tco;
+        },
+        _ => {
+          let f = eval(a0.clone(), env.clone()).map_err(|e| e.at(item_span(&ast, 0)))?;
+          let args = eval_items(&ast, 1, &env)?;
+          match f {
+            Func(_,_) | Keyword(_) | Set(_,_) => f.apply(args),
+            MalFunc{ast: mast, env: menv, params, ..} => {
+              env = env_bind(Some(menv), (*params).clone(), args, eval)?;
+              ctx.call = Some(ast.clone());
+              lp = None;
+              ast = (*mast).clone();
+              continue 'tco;
+            },
+            _ => error_kind(ErrKind::Type, "attempt to call non-function"),
+          }
+        }
+      }
+    },
+    _ => eval_ast(&ast, &env),
+  };
+
+  break;
+
+  } // end 'tco loop
+
+  ret
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -0,0 +1,159 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::{BufRead,BufReader,Lines,Write};
+use std::path::{Path,PathBuf};
+
+use types::{MalVal,MalRet,MalErr,ErrKind,keyword,keyword_map,lazy_seq,lazy_cons};
+use types::MalVal::{Nil,Bool,Int,Str,List};
+use printer::pr_seq;
+use core::{flag,str_arg};
+
+// the reason keyword for an io error kind; kinds not named here are
+// :other, so a new kind in std never changes what mal code sees
+fn io_reason(kind: io::ErrorKind) -> &'static str {
+  match kind {
+    io::ErrorKind::NotFound => "not-found",
+    io::ErrorKind::PermissionDenied => "permission-denied",
+    io::ErrorKind::AlreadyExists => "already-exists",
+    io::ErrorKind::IsADirectory => "is-a-directory",
+    io::ErrorKind::NotADirectory => "not-a-directory",
+    io::ErrorKind::DirectoryNotEmpty => "directory-not-empty",
+    io::ErrorKind::ReadOnlyFilesystem => "read-only-filesystem",
+    io::ErrorKind::StorageFull => "storage-full",
+    io::ErrorKind::InvalidInput => "invalid-input",
+    io::ErrorKind::InvalidData => "invalid-data",
+    io::ErrorKind::UnexpectedEof => "unexpected-eof",
+    io::ErrorKind::BrokenPipe => "broken-pipe",
+    io::ErrorKind::Interrupted => "interrupted",
+    io::ErrorKind::TimedOut => "timed-out",
+    io::ErrorKind::Unsupported => "unsupported",
+    _ => "other",
+  }
+}
+
+// an io error for path; the data map holds the path and the reason as a
+// keyword such as :not-found, so callers can tell failures apart
+pub fn io_error(path: &str, e: io::Error) -> MalErr {
+  let mut err = MalErr::new(ErrKind::Io, &format!("{}: {}", path, e));
+  err.data = keyword_map(vec![("path", Str(path.to_string())),
+                              ("reason", keyword(io_reason(e.kind())))]);
+  err
+}
+
+fn path_str(p: &Path) -> MalVal {
+  Str(p.to_string_lossy().into_owned())
+}
+
+// (spit f x) or (spit f x {:append true}); x is written as by str
+pub fn spit(a: &[MalVal]) -> MalRet {
+  let path = str_arg("spit", &a[0])?;
+  let append = flag(a.get(2), "append")?;
+  let s = pr_seq(&a[1..2], false, "", "", "")?;
+  fs::OpenOptions::new().write(true).create(true).append(append).truncate(!append)
+    .open(path)
+    .and_then(|mut f| f.write_all(s.as_bytes()))
+    .map_err(|e| io_error(path, e))?;
+  Ok(Nil)
+}
+
+// a lazy seq of the lines of a file, without their line endings; the
+// file is read as the seq is walked
+pub fn read_lines(a: &[MalVal]) -> MalRet {
+  fn lines_from(path: Rc<str>, lines: Rc<RefCell<Lines<BufReader<fs::File>>>>) -> MalVal {
+    lazy_seq(move || {
+      let next = lines.borrow_mut().next();
+      match next {
+        Some(Ok(line)) => Ok(lazy_cons(Str(line), lines_from(path.clone(), lines.clone()))),
+        Some(Err(e)) => Err(io_error(&path, e)),
+        None => Ok(Nil),
+      }
+    })
+  }
+  let path = str_arg("read-lines", &a[0])?;
+  let f = fs::File::open(path).map_err(|e| io_error(path, e))?;
+  Ok(lines_from(Rc::from(path), Rc::new(RefCell::new(BufReader::new(f).lines()))))
+}
+
+pub fn file_exists_q(a: &[MalVal]) -> MalRet {
+  Ok(Bool(Path::new(str_arg("file-exists?", &a[0])?).exists()))
+}
+
+pub fn dir_q(a: &[MalVal]) -> MalRet {
+  Ok(Bool(Path::new(str_arg("dir?", &a[0])?).is_dir()))
+}
+
+pub fn file_size(a: &[MalVal]) -> MalRet {
+  let path = str_arg("file-size", &a[0])?;
+  let md = fs::metadata(path).map_err(|e| io_error(path, e))?;
+  Ok(Int(md.len() as i64))
+}
+
+// the sorted names of the entries in a directory
+pub fn list_dir(a: &[MalVal]) -> MalRet {
+  let path = str_arg("list-dir", &a[0])?;
+  let mut names = fs::read_dir(path)
+    .and_then(|rd| rd.map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+                     .collect::<io::Result<Vec<String>>>())
+    .map_err(|e| io_error(path, e))?;
+  names.sort();
+  Ok(list!(names.into_iter().map(Str).collect::<Vec<MalVal>>()))
+}
+
+// makes a directory and any missing parents
+pub fn mkdir(a: &[MalVal]) -> MalRet {
+  let path = str_arg("mkdir", &a[0])?;
+  fs::create_dir_all(path).map_err(|e| io_error(path, e))?;
+  Ok(Nil)
+}
+
+// deletes a file or an empty directory
+pub fn delete_file(a: &[MalVal]) -> MalRet {
+  let path = str_arg("delete-file", &a[0])?;
+  let res = if Path::new(path).is_dir() { fs::remove_dir(path) } else { fs::remove_file(path) };
+  res.map_err(|e| io_error(path, e))?;
+  Ok(Nil)
+}
+
+pub fn rename_file(a: &[MalVal]) -> MalRet {
+  let from = str_arg("rename-file", &a[0])?;
+  let to = str_arg("rename-file", &a[1])?;
+  fs::rename(from, to).map_err(|e| io_error(from, e))?;
+  Ok(Nil)
+}
+
+pub fn path_join(a: &[MalVal]) -> MalRet {
+  let mut p = PathBuf::new();
+  for x in a.iter() {
+    p.push(str_arg("path-join", x)?);
+  }
+  Ok(path_str(&p))
+}
+
+// the last component of a path, or nil when it has none, as for "/"
+pub fn basename(a: &[MalVal]) -> MalRet {
+  Ok(match Path::new(str_arg("basename", &a[0])?).file_name() {
+    Some(n) => Str(n.to_string_lossy().into_owned()),
+    None    => Nil,
+  })
+}
+
+// the path without its last component; "." for a bare name and nil for
+// a root
+pub fn dirname(a: &[MalVal]) -> MalRet {
+  Ok(match Path::new(str_arg("dirname", &a[0])?).parent() {
+    Some(p) if p.as_os_str().is_empty() => Str(".".to_string()),
+    Some(p) => path_str(p),
+    None    => Nil,
+  })
+}
+
+// the path made absolute against the current directory; the file need
+// not exist and symlinks are not resolved
+pub fn abs_path(a: &[MalVal]) -> MalRet {
+  let path = str_arg("abs-path", &a[0])?;
+  Ok(path_str(&::std::path::absolute(path).map_err(|e| io_error(path, e))?))
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -0,0 +1,233 @@
+use std::rc::Rc;
+
+use types::{MalVal,MalRet,ErrKind,error_kind};
+use types::MalVal::{Nil,Sym,List};
+use reader::read_all;
+use env::{Env,env_new,env_get,env_sets};
+use eval::eval;
+use core;
+
+// the parts of the core library written in mal itself
+const PRELUDE: &[&str] = &[
+  "(def! *host-language* \"rust\")",
+  "(def! not (fn* (a) (if a false true)))",
+  "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
+  "(def! *gensym-counter* (atom 0))",
+  "(def! gensym (fn* [] (symbol (str \"G__\" (swap! *gensym-counter* (fn* [x] (+ 1 x)))))))",
+  "(defmacro! or (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) (let* (condvar (gensym)) `(let* (~condvar ~(first xs)) (if ~condvar ~condvar (or ~@(rest xs)))))))))",
+  "(defmacro! lazy-seq (fn* (& body) `(lazy-seq* (fn* [] ~@body))))",
+];
+
+// defined along with the host builtins, as it reads files
+const LOAD_FILE: &str = "(def! load-file (fn* (f) (eval (cons 'do (read-file f)))))";
+
+// the core builtins that reach outside the interpreter: the file
+// system, the process environment, subprocesses, console input and exit
+const HOST_FNS: &[&str] = &[
+  "readline", "slurp", "read-file", "spit", "read-lines", "file-exists?", "dir?",
+  "file-size", "list-dir", "mkdir", "delete-file", "rename-file", "abs-path",
+  "sh", "getenv", "setenv", "exit",
+];
+
+// a mal interpreter for embedding: a root environment holding the core
+// library, in which code is evaluated and values are defined
+pub struct Interpreter {
+  env: Env,
+}
+
+impl Interpreter {
+  pub fn new() -> Interpreter {
+    Interpreter::with_ns(true)
+  }
+
+  // an interpreter without HOST_FNS, for running untrusted code:
+  // its scripts can only reach the host through what is defined in it
+  pub fn sandboxed() -> Interpreter {
+    Interpreter::with_ns(false)
+  }
+
+  fn with_ns(host: bool) -> Interpreter {
+    let env = env_new(None);
+    for (k, v) in core::ns() {
+      if host || !HOST_FNS.contains(&k) {
+        env_sets(&env, k, v);
+      }
+    }
+    env_sets(&env, "*ARGV*", list![]);
+    env_sets(&env, "*command-line-args*", list![]);
+    let interp = Interpreter{env};
+    for src in PRELUDE {
+      interp.eval_str(src).expect("prelude failed to evaluate");
+    }
+    if host {
+      interp.eval_str(LOAD_FILE).expect("prelude failed to evaluate");
+    }
+    interp
+  }
+
+  pub fn env(&self) -> &Env {
+    &self.env
+  }
+
+  // evaluates every form in src, returning the value of the last one
+  pub fn eval_str(&self, src: &str) -> MalRet {
+    self.eval_forms(read_all(src.to_string(), None)?)
+  }
+
+  // evaluates every form in the file at path, returning the value of the
+  // last one
+  pub fn eval_file(&self, path: &str) -> MalRet {
+    self.eval_forms(read_all(core::read_file(path)?, Some(path))?)
+  }
+
+  fn eval_forms(&self, forms: Vec<MalVal>) -> MalRet {
+    let mut ret = Nil;
+    for form in forms {
+      ret = eval(form, self.env.clone())?;
+    }
+    Ok(ret)
+  }
+
+  // binds name to val in the root environment
+  pub fn define(&self, name: &str, val: MalVal) {
+    env_sets(&self.env, name, val);
+  }
+
+  // the value bound to name, if it is defined
+  pub fn get(&self, name: &str) -> Option<MalVal> {
+    env_get(&self.env, &Sym(name.to_string())).ok()
+  }
+
+  // applies the function bound to name to args
+  pub fn call(&self, name: &str, args: Vec<MalVal>) -> MalRet {
+    match self.get(name) {
+      Some(f) => f.apply(args),
+      None => error_kind(ErrKind::Unbound, &format!("'{}' not found", name)),
+    }
+  }
+}
+
+impl Default for Interpreter {
+  fn default() -> Interpreter {
+    Interpreter::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::Cell;
+  use super::*;
+  use types::{MalErr,keyword};
+  use types::MalVal::{Int,Str};
+  use env::env_set_fn;
+  use convert::wrap_fn;
+
+  #[test]
+  fn eval_str() {
+    let interp = Interpreter::new();
+    assert_eq!(interp.eval_str("(+ 1 2)").unwrap(), Int(3));
+    assert_eq!(interp.eval_str("(def! x 5) (* x 2)").unwrap(), Int(10));
+    assert_eq!(interp.eval_str("").unwrap(), Nil);
+    assert_eq!(interp.eval_str("(cond false 1 :else (or nil 2))").unwrap(), Int(2));
+    assert_eq!(interp.eval_str("(abc)").unwrap_err().kind, ErrKind::Unbound);
+    assert_eq!(interp.eval_str("(+ 1").unwrap_err().kind, ErrKind::Reader);
+  }
+
+  #[test]
+  fn define_and_get() {
+    let interp = Interpreter::new();
+    interp.define("limit", Int(3));
+    interp.define("name", Str("mal".to_string()));
+    assert_eq!(interp.eval_str("(str name \"-\" limit)").unwrap(), Str("mal-3".to_string()));
+    interp.eval_str("(def! cfg {:port (* limit 1000)})").unwrap();
+    let cfg = interp.get("cfg").unwrap();
+    assert_eq!(cfg.pr_str(true), "{:port 3000}");
+    assert_eq!(interp.get("limit"), Some(Int(3)));
+    assert_eq!(interp.get("no-such-thing"), None);
+    // each interpreter has its own root env
+    assert_eq!(Interpreter::new().get("limit"), None);
+  }
+
+  #[test]
+  fn call() {
+    let interp = Interpreter::new();
+    interp.eval_str("(def! add (fn* [a b] (+ a b)))").unwrap();
+    assert_eq!(interp.call("add", vec![Int(1), Int(2)]).unwrap(), Int(3));
+    assert_eq!(interp.call("count", vec![list!(vec![Int(1)])]).unwrap(), Int(1));
+    assert_eq!(interp.call("add", vec![Int(1)]).unwrap_err().kind, ErrKind::Arity);
+    assert_eq!(interp.call("nope", vec![]).unwrap_err().kind, ErrKind::Unbound);
+    interp.define("twice", wrap_fn("twice", |n: i64| Ok(n * 2)));
+    assert_eq!(interp.eval_str("(twice (add 1 2))").unwrap(), Int(6));
+    let e = interp.call("twice", vec![keyword("x")]).unwrap_err();
+    assert_eq!(e.msg, "twice: argument 1: expected an integer, got :x");
+  }
+
+  #[test]
+  fn malformed_special_forms() {
+    let interp = Interpreter::sandboxed();
+    assert_eq!(interp.eval_str("()").unwrap().pr_str(true), "()");
+    assert_eq!(interp.eval_str("(do)").unwrap(), Nil);
+    for src in ["(def!)", "(def! x)", "(let*)", "(let* [a 1])", "(loop*)", "(quote)",
+                "(quasiquote)", "`(unquote)", "(defmacro! m)", "(macroexpand)", "(if)",
+                "(if 1)", "(if 1 2 3 4)", "(fn*)", "(fn* [a])", "(eval)", "(try*)",
+                "(try* 1 (catch*))", "(try* 1 (catch* e))"] {
+      let e = interp.eval_str(src).unwrap_err();
+      assert_eq!(e.kind, ErrKind::Arity, "{}", src);
+    }
+    assert!(interp.eval_str("(try* 1 2)").is_err());
+    assert!(interp.eval_str("(let* 1 2)").is_err());
+    assert!(interp.eval_str("(loop* 1 2)").is_err());
+    assert_eq!(interp.eval_str("(def!) 1").unwrap_err().msg,
+               "wrong number of args (0) passed to def!");
+  }
+
+  #[test]
+  fn sandboxed() {
+    let interp = Interpreter::sandboxed();
+    for name in HOST_FNS.iter().chain(&["load-file"]) {
+      assert_eq!(interp.get(name), None, "{} is defined", name);
+      let e = interp.eval_str(&format!("({})", name)).unwrap_err();
+      assert_eq!(e.kind, ErrKind::Unbound);
+    }
+    assert_eq!(interp.eval_str("(map (fn* [x] (* x x)) (range 3))").unwrap().pr_str(true),
+               "(0 1 4)");
+    // the host can still hand it functions of its own
+    interp.define("host-name", wrap_fn("host-name", || Ok("test")));
+    assert_eq!(interp.eval_str("(host-name)").unwrap(), Str("test".to_string()));
+    assert!(Interpreter::new().get("exit").is_some());
+  }
+
+  #[test]
+  fn closures_from_env_set_fn() {
+    let interp = Interpreter::new();
+    let count = Rc::new(Cell::new(0));
+    let c = count.clone();
+    env_set_fn(interp.env(), "bump!", 0, Some(1), move |a: &[MalVal]| {
+      let by = match a.first() {
+        Some(Int(n)) => *n,
+        Some(v) => return error_kind(ErrKind::Type, &format!("bump!: not a number: {}", v.pr_str(true))),
+        None => 1,
+      };
+      c.set(c.get() + by);
+      Ok(Int(c.get()))
+    });
+    assert_eq!(interp.eval_str("(bump!) (bump! 5)").unwrap(), Int(6));
+    assert_eq!(interp.eval_str("(map (fn* [_] (bump!)) [1 2])").unwrap().pr_str(true), "(7 8)");
+    assert_eq!(count.get(), 8);
+
+    let e = interp.eval_str("(bump! 1 2)").unwrap_err();
+    assert_eq!(e.kind, ErrKind::Arity);
+    let e = interp.eval_str("(bump! :x)").unwrap_err();
+    assert_eq!((e.kind, e.msg.as_str()), (ErrKind::Type, "bump!: not a number: :x"));
+    assert_eq!(count.get(), 8);
+
+    env_set_fn(interp.env(), "fail", 0, None, |_: &[MalVal]| {
+      Err(MalErr::new(ErrKind::Io, "disk full"))
+    });
+    assert_eq!(interp.eval_str("(fail)").unwrap_err().msg, "disk full");
+    assert_eq!(interp.eval_str("(try* (fail 1 2) (catch* e [e (get *error* :kind)]))")
+                 .unwrap().pr_str(true), "[\"disk full\" :io]");
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
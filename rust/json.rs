@@ -0,0 +1,188 @@
+use std::rc::Rc;
+use std::fmt;
+use std::cell::Cell;
+use num_bigint::BigInt;
+use serde::de::{self,Visitor,SeqAccess,MapAccess,DeserializeSeed};
+use serde_json;
+
+use types::{MalVal,MalVec,MalMap,MalRet,MalErr,ErrKind,bigint,error_kind};
+use types::MalVal::{Nil,Bool,Int,Float,Str,List,Vector,Hash,Set,LazySeq,Atom};
+use core::flag;
+
+// the text of each number in the json s, in order; serde_json reads an
+// integer too big for a u64 as a float, so json-parse takes its digits
+// from here instead
+fn numbers(s: &str) -> Vec<&str> {
+  let b = s.as_bytes();
+  let mut nums = vec![];
+  let mut i = 0;
+  while i < b.len() {
+    match b[i] {
+      b'"' => {
+        i += 1;
+        while i < b.len() && b[i] != b'"' {
+          if b[i] == b'\\' {
+            i += 1;
+          }
+          i += 1;
+        }
+        i += 1;
+      },
+      b'-' | b'0'..=b'9' => {
+        let start = i;
+        while i < b.len() && matches!(b[i], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+          i += 1;
+        }
+        nums.push(&s[start..i]);
+      },
+      _ => i += 1,
+    }
+  }
+  nums
+}
+
+// reads a json value, counting off the numbers serde_json visits
+// against their text
+#[derive(Clone, Copy)]
+struct Json<'a> {
+  nums: &'a [&'a str],
+  next: &'a Cell<usize>,
+}
+
+impl<'a> Json<'a> {
+  fn next_number(&self) -> Option<&'a str> {
+    let i = self.next.get();
+    self.next.set(i + 1);
+    self.nums.get(i).cloned()
+  }
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for Json<'a> {
+  type Value = MalVal;
+
+  fn deserialize<D: de::Deserializer<'de>>(self, d: D) -> Result<MalVal,D::Error> {
+    d.deserialize_any(self)
+  }
+}
+
+// arrays are read as vectors and objects as maps with string keys
+impl<'de, 'a> Visitor<'de> for Json<'a> {
+  type Value = MalVal;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a json value")
+  }
+
+  fn visit_bool<E>(self, b: bool) -> Result<MalVal,E> { Ok(Bool(b)) }
+  fn visit_str<E>(self, s: &str) -> Result<MalVal,E> { Ok(Str(s.to_string())) }
+  fn visit_string<E>(self, s: String) -> Result<MalVal,E> { Ok(Str(s)) }
+  fn visit_unit<E>(self) -> Result<MalVal,E> { Ok(Nil) }
+
+  fn visit_i64<E>(self, i: i64) -> Result<MalVal,E> {
+    self.next_number();
+    Ok(Int(i))
+  }
+
+  fn visit_u64<E>(self, u: u64) -> Result<MalVal,E> {
+    self.next_number();
+    Ok(bigint(BigInt::from(u)))
+  }
+
+  // an integer here overflowed a u64, so it is read from its text
+  fn visit_f64<E>(self, f: f64) -> Result<MalVal,E> {
+    match self.next_number().and_then(|s| s.parse::<BigInt>().ok()) {
+      Some(b) => Ok(bigint(b)),
+      None    => Ok(Float(f)),
+    }
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MalVal,A::Error> {
+    let mut items = vec![];
+    while let Some(x) = seq.next_element_seed(self)? {
+      items.push(x);
+    }
+    Ok(vector!(items))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MalVal,A::Error> {
+    let mut hm = MalMap::default();
+    while let Some(k) = map.next_key::<String>()? {
+      hm.insert(Str(k), map.next_value_seed(self)?);
+    }
+    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+  }
+}
+
+fn read_json(s: &str) -> Result<MalVal,serde_json::Error> {
+  let nums = numbers(s);
+  let next = Cell::new(0);
+  let mut d = serde_json::Deserializer::from_str(s);
+  let v = Json{nums: &nums, next: &next}.deserialize(&mut d)?;
+  d.end()?;
+  Ok(v)
+}
+
+// string keys of every map in v become keywords
+fn keywordize(v: MalVal) -> MalVal {
+  match v {
+    Hash(hm,_) => {
+      let hm: MalMap = hm.iter().map(|(k, v)| {
+        let k = match k {
+          Str(s) => ::types::keyword(s),
+          _ => k.clone(),
+        };
+        (k, keywordize(v.clone()))
+      }).collect();
+      Hash(Rc::new(hm), Rc::new(Nil))
+    },
+    Vector(v,_) => vector!(v.iter().cloned().map(keywordize).collect::<MalVec>()),
+    _ => v,
+  }
+}
+
+// (json-parse s) or (json-parse s {:keywordize-keys true}); objects are
+// read as hash-maps and arrays as vectors
+pub fn json_parse(a: &[MalVal]) -> MalRet {
+  let s = match a[0] {
+    Str(ref s) => s,
+    _ => return error_kind(ErrKind::Type, &format!("json-parse: expected a string, got {}",
+                                                   a[0].pr_str(true))),
+  };
+  let v = read_json(s)
+    .map_err(|e| MalErr::new(ErrKind::Reader, &format!("json-parse: {}", e)))?;
+  match flag(a.get(1), "keywordize-keys")? {
+    true  => Ok(keywordize(v)),
+    false => Ok(v),
+  }
+}
+
+// serde_json writes NaN and the infinities as null, so json-str checks
+// for them first and fails instead
+fn check_finite(v: &MalVal) -> Result<(),MalErr> {
+  match v {
+    Float(f) if !f.is_finite() => {
+      Err(MalErr::new(ErrKind::Type, &format!("json-str: cannot serialize {}", v.pr_str(true))))
+    },
+    List(l,_) => l.iter().try_for_each(check_finite),
+    Vector(l,_) => l.iter().try_for_each(check_finite),
+    Set(hs,_) => hs.iter().try_for_each(check_finite),
+    Hash(hm,_) => hm.values().try_for_each(check_finite),
+    LazySeq(..) => v.seq_vec()?.iter().try_for_each(check_finite),
+    MalVal::Tagged(_,v) => check_finite(v),
+    Atom(a) => check_finite(&a.borrow()),
+    _ => Ok(()),
+  }
+}
+
+// (json-str x) or (json-str x {:pretty true}); keywords and symbols are
+// written as strings, lists and sets as arrays
+pub fn json_str(a: &[MalVal]) -> MalRet {
+  check_finite(&a[0])?;
+  let s = match flag(a.get(1), "pretty")? {
+    true  => serde_json::to_string_pretty(&a[0]),
+    false => serde_json::to_string(&a[0]),
+  };
+  s.map(Str).map_err(|e| MalErr::new(ErrKind::Type, &format!("json-str: {}", e)))
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -0,0 +1,37 @@
+// the stepA interpreter as a library, for embedding mal in rust programs
+
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+extern crate itertools;
+extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+extern crate im_rc;
+extern crate rustyline;
+extern crate serde;
+extern crate serde_json;
+
+#[macro_use]
+pub mod types;
+mod reader;
+mod printer;
+pub mod env;
+mod numeric;
+mod files;
+mod regexp;
+mod strings;
+mod json;
+mod core;
+mod eval;
+mod interpreter;
+pub mod convert;
+pub mod serialize;
+
+pub use types::{MalVal,MalArgs,MalRet,MalErr,ErrKind,func,format_error};
+pub use interpreter::Interpreter;
+pub use convert::{ToMal,FromMal,wrap_fn};
+pub use serialize::{to_value,from_value};
+
+// vim: ts=2:sw=2:expandtab
@@ -0,0 +1,264 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+use num_bigint::BigInt;
+use num_traits::{Zero,One,ToPrimitive,FromPrimitive,pow};
+use num_rational::BigRational;
+
+use types::{MalVal,MalRet,MalErr,ErrKind,error_kind,bigint,ratio};
+use types::MalVal::{Bool,Int,Float,Ratio};
+
+fn not_a_number(v: &MalVal) -> MalErr {
+  MalErr::new(ErrKind::Type, &format!("expected a number, got {}", v.pr_str(true)))
+}
+
+fn to_f64(v: &MalVal) -> Result<f64,MalErr> {
+  match v {
+    Int(i)   => Ok(*i as f64),
+    Float(f) => Ok(*f),
+    MalVal::BigInt(b) => Ok(b.to_f64().unwrap_or(f64::NAN)),
+    Ratio(r) => Ok(r.to_f64().unwrap_or(f64::NAN)),
+    _ => Err(not_a_number(v)),
+  }
+}
+
+fn to_big(v: &MalVal) -> Result<BigInt,MalErr> {
+  match v {
+    Int(i) => Ok(BigInt::from(*i)),
+    MalVal::BigInt(b) => Ok((**b).clone()),
+    _ => Err(not_a_number(v)),
+  }
+}
+
+fn to_ratio(v: &MalVal) -> Result<BigRational,MalErr> {
+  match v {
+    Ratio(r) => Ok((**r).clone()),
+    _ => Ok(BigRational::from_integer(to_big(v)?)),
+  }
+}
+
+fn overflow() -> MalErr {
+  MalErr::new(ErrKind::Arithmetic, "integer overflow")
+}
+
+// an arithmetic operation on each kind of number; i returns None when
+// the result does not fit in an i64
+struct Op {
+  i: fn(i64, i64) -> Option<i64>,
+  b: fn(&BigInt, &BigInt) -> BigInt,
+  r: fn(&BigRational, &BigRational) -> BigRational,
+  f: fn(f64, f64) -> f64,
+}
+
+const ADD: Op = Op { i: i64::checked_add, b: |x,y| x+y, r: |x,y| x+y, f: |x,y| x+y };
+const SUB: Op = Op { i: i64::checked_sub, b: |x,y| x-y, r: |x,y| x-y, f: |x,y| x-y };
+const MUL: Op = Op { i: i64::checked_mul, b: |x,y| x*y, r: |x,y| x*y, f: |x,y| x*y };
+const QUOT: Op = Op {
+  i: i64::checked_div,
+  b: |x,y| x/y,
+  r: |x,y| (x/y).trunc(),
+  f: |x,y| (x/y).trunc(),
+};
+const REM: Op = Op {
+  i: |x,y| Some(x.wrapping_rem(y)),
+  b: |x,y| x%y,
+  r: |x,y| x - y*(x/y).trunc(),
+  f: |x,y| x%y,
+};
+const MOD: Op = Op {
+  i: |x,y| { let m = x.wrapping_rem(y); Some(if m != 0 && (m < 0) != (y < 0) { m+y } else { m }) },
+  b: |x,y| { let m = x%y; if !m.is_zero() && (m.sign() != y.sign()) { m+y } else { m } },
+  r: |x,y| x - y*(x/y).floor(),
+  f: |x,y| x - y*(x/y).floor(),
+};
+
+// applies op to a pair of numbers: floats are contagious, then ratios,
+// then bigints, which stay big even when the result is small; an int
+// result that overflows is promoted when promote is set and is an
+// error otherwise
+fn arith(a: &MalVal, b: &MalVal, op: &Op, promote: bool) -> MalRet {
+  match (a, b) {
+    (Int(i), Int(j)) => match (op.i)(*i, *j) {
+      Some(r)       => Ok(Int(r)),
+      None if promote => Ok(bigint((op.b)(&BigInt::from(*i), &BigInt::from(*j)))),
+      None          => Err(overflow()),
+    },
+    (Float(_), _) | (_, Float(_)) => Ok(Float((op.f)(to_f64(a)?, to_f64(b)?))),
+    (Ratio(_), _) | (_, Ratio(_)) => Ok(ratio((op.r)(&to_ratio(a)?, &to_ratio(b)?))),
+    _ => Ok(MalVal::BigInt(Rc::new((op.b)(&to_big(a)?, &to_big(b)?)))),
+  }
+}
+
+// integer division by zero is an error; with a float on either side the
+// result is infinite or NaN instead
+fn check_divisor(a: &MalVal, b: &MalVal) -> Result<(),MalErr> {
+  match (a, b) {
+    (Float(_), _) | (_, Float(_)) => Ok(()),
+    (_, Int(0)) => Err(MalErr::new(ErrKind::Arithmetic, "divide by zero")),
+    (_, MalVal::BigInt(d)) if d.is_zero() => {
+      Err(MalErr::new(ErrKind::Arithmetic, "divide by zero"))
+    },
+    _ => Ok(()),
+  }
+}
+
+// folds the args with op; with one arg x it is (op id x) and with none
+// it is id, as for Clojure's + - * /
+fn fold(a: &[MalVal], id: MalVal, op: &dyn Fn(&MalVal, &MalVal) -> MalRet) -> MalRet {
+  match a.split_first() {
+    Some((x, rest)) if !rest.is_empty() => {
+      rest.iter().try_fold(x.clone(), |acc, y| op(&acc, y))
+    },
+    Some((x, _)) => op(&id, x),
+    None         => Ok(id),
+  }
+}
+
+pub fn add(a: &[MalVal]) -> MalRet { fold(a, Int(0), &|x, y| arith(x, y, &ADD, false)) }
+pub fn sub(a: &[MalVal]) -> MalRet { fold(a, Int(0), &|x, y| arith(x, y, &SUB, false)) }
+pub fn mul(a: &[MalVal]) -> MalRet { fold(a, Int(1), &|x, y| arith(x, y, &MUL, false)) }
+
+pub fn add_p(a: &[MalVal]) -> MalRet { fold(a, Int(0), &|x, y| arith(x, y, &ADD, true)) }
+pub fn sub_p(a: &[MalVal]) -> MalRet { fold(a, Int(0), &|x, y| arith(x, y, &SUB, true)) }
+pub fn mul_p(a: &[MalVal]) -> MalRet { fold(a, Int(1), &|x, y| arith(x, y, &MUL, true)) }
+
+// exact division, giving a ratio when ints do not divide evenly
+fn divide(a: &MalVal, b: &MalVal) -> MalRet {
+  check_divisor(a, b)?;
+  match (a, b) {
+    (Int(i), Int(j)) if i.checked_rem(*j) == Some(0) => Ok(Int(i / j)),
+    (Float(_), _) | (_, Float(_)) => Ok(Float(to_f64(a)? / to_f64(b)?)),
+    _ => Ok(ratio(to_ratio(a)? / to_ratio(b)?)),
+  }
+}
+
+pub fn div(a: &[MalVal]) -> MalRet { fold(a, Int(1), &divide) }
+
+pub fn num_cmp(a: &MalVal, b: &MalVal) -> Result<Option<Ordering>,MalErr> {
+  match (a, b) {
+    (Int(i), Int(j)) => Ok(Some(i.cmp(j))),
+    (Float(_), _) | (_, Float(_)) => Ok(to_f64(a)?.partial_cmp(&to_f64(b)?)),
+    (Ratio(_), _) | (_, Ratio(_)) => Ok(Some(to_ratio(a)?.cmp(&to_ratio(b)?))),
+    _ => Ok(Some(to_big(a)?.cmp(&to_big(b)?))),
+  }
+}
+
+// true when every adjacent pair of args compares as accepted by ok;
+// NaN compares as nothing
+fn cmp_chain(a: &[MalVal], ok: fn(Ordering) -> bool) -> MalRet {
+  for x in a.iter() {
+    to_f64(x)?;
+  }
+  for w in a.windows(2) {
+    match num_cmp(&w[0], &w[1])? {
+      Some(o) if ok(o) => (),
+      _ => return Ok(Bool(false)),
+    }
+  }
+  Ok(Bool(true))
+}
+
+pub fn lt(a: &[MalVal]) -> MalRet { cmp_chain(a, |o| o == Ordering::Less) }
+pub fn lte(a: &[MalVal]) -> MalRet { cmp_chain(a, |o| o != Ordering::Greater) }
+pub fn gt(a: &[MalVal]) -> MalRet { cmp_chain(a, |o| o == Ordering::Greater) }
+pub fn gte(a: &[MalVal]) -> MalRet { cmp_chain(a, |o| o != Ordering::Less) }
+
+pub fn double(a: &[MalVal]) -> MalRet {
+  Ok(Float(to_f64(&a[0])?))
+}
+
+// truncates towards zero
+pub fn int(a: &[MalVal]) -> MalRet {
+  let i = match a[0] {
+    Float(f) if f.is_nan() || f.is_infinite() => {
+      return error_kind(ErrKind::Type, &format!("cannot convert {} to an int",
+                                                Float(f).pr_str(true)))
+    },
+    Float(f) => BigInt::from_f64(f.trunc()).unwrap(),
+    _ => to_ratio(&a[0])?.trunc().to_integer(),
+  };
+  i.to_i64().map(Int).ok_or_else(overflow)
+}
+
+// applies rop to a ratio or fop to a float; integers are already whole
+fn whole(a: &MalVal, rop: fn(&BigRational) -> BigRational, fop: fn(f64) -> f64) -> MalRet {
+  match a {
+    Float(f) => Ok(Float(fop(*f))),
+    Ratio(r) => Ok(ratio(rop(r))),
+    _        => { to_big(a)?; Ok(a.clone()) },
+  }
+}
+
+pub fn floor(a: &[MalVal]) -> MalRet { whole(&a[0], BigRational::floor, f64::floor) }
+pub fn ceil(a: &[MalVal]) -> MalRet { whole(&a[0], BigRational::ceil, f64::ceil) }
+
+// rounds half up, like Clojure's round
+pub fn round(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Float(f) => match BigInt::from_f64((f + 0.5).floor()) {
+      Some(b) => Ok(bigint(b)),
+      None    => error_kind(ErrKind::Type, &format!("cannot round {}",
+                                                    Float(f).pr_str(true))),
+    },
+    _ => whole(&a[0], |r| (r + BigRational::new(One::one(), BigInt::from(2))).floor(),
+               f64::floor),
+  }
+}
+
+// quotient truncated towards zero
+pub fn quot(a: &[MalVal]) -> MalRet {
+  check_divisor(&a[0], &a[1])?;
+  arith(&a[0], &a[1], &QUOT, false)
+}
+
+// remainder with the sign of the dividend
+pub fn rem(a: &[MalVal]) -> MalRet {
+  check_divisor(&a[0], &a[1])?;
+  arith(&a[0], &a[1], &REM, false)
+}
+
+// modulus with the sign of the divisor
+pub fn modulo(a: &[MalVal]) -> MalRet {
+  check_divisor(&a[0], &a[1])?;
+  arith(&a[0], &a[1], &MOD, false)
+}
+
+// the exact ratio of the shortest decimal that reads back as a float,
+// so (rationalize 0.1) is 1/10
+pub fn rationalize(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Float(f) if f.is_nan() || f.is_infinite() => {
+      error_kind(ErrKind::Type, &format!("cannot rationalize {}", Float(f).pr_str(true)))
+    },
+    Float(f) => {
+      let s = format!("{:?}", f);
+      let (mantissa, exp) = match s.find('e') {
+        Some(i) => (&s[..i], s[i+1..].parse::<i64>().unwrap()),
+        None    => (&s[..], 0),
+      };
+      let (digits, scale) = match mantissa.find('.') {
+        Some(i) => (format!("{}{}", &mantissa[..i], &mantissa[i+1..]),
+                    (mantissa.len() - i - 1) as i64),
+        None    => (mantissa.to_string(), 0),
+      };
+      let n = BigRational::from_integer(digits.parse().unwrap());
+      let e = exp - scale;
+      let ten = BigRational::from_integer(BigInt::from(10));
+      if e < 0 {
+        Ok(ratio(n / pow(ten, (-e) as usize)))
+      } else {
+        Ok(ratio(n * pow(ten, e as usize)))
+      }
+    },
+    _ => { to_ratio(&a[0])?; Ok(a[0].clone()) },
+  }
+}
+
+pub fn numerator(a: &[MalVal]) -> MalRet {
+  Ok(bigint(to_ratio(&a[0])?.numer().clone()))
+}
+
+pub fn denominator(a: &[MalVal]) -> MalRet {
+  Ok(bigint(to_ratio(&a[0])?.denom().clone()))
+}
+
+// vim: ts=2:sw=2:expandtab
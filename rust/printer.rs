@@ -1,5 +1,5 @@
-use types::MalVal;
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector,Hash,Func,MalFunc,Atom};
+use types::{MalVal,MalErr,format_error};
+use types::MalVal::{Nil,Bool,Int,Float,Str,Keyword,Sym,List,Vector,Hash,Set,LazySeq,Tagged,Regex,Func,MalFunc,Atom,Reduced};
 
 fn escape_str(s: &str) -> String {
   s.chars().map(|c| {
@@ -12,49 +12,104 @@ fn escape_str(s: &str) -> String {
   }).collect::<Vec<String>>().join("")
 }
 
+// a regex pattern can't contain \" (the regex crate rejects it), so
+// each bare quote is escaped and escape pairs are left alone
+fn escape_regex(s: &str) -> String {
+  let mut res = String::new();
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '"'  => res.push_str("\\\""),
+      '\\' => {
+        res.push(c);
+        res.extend(chars.next());
+      },
+      _ => res.push(c),
+    }
+  }
+  res
+}
+
 impl MalVal {
+  // for messages: a lazy seq that fails to realize is shown as
+  // #<lazy-seq error: ...>
   pub fn pr_str(&self, print_readably: bool) -> String {
-    match self {
+    self.pr(print_readably, false).unwrap_or_else(format_error)
+  }
+
+  // for output: a lazy seq that fails to realize fails the print, so
+  // that the error reaches try*
+  pub fn try_pr_str(&self, print_readably: bool) -> Result<String,MalErr> {
+    self.pr(print_readably, true)
+  }
+
+  fn pr(&self, print_readably: bool, strict: bool) -> Result<String,MalErr> {
+    Ok(match self {
       Nil         => String::from("nil"),
       Bool(true)  => String::from("true"),
       Bool(false) => String::from("false"),
       Int(i)      => format!("{}", i),
-      //Float(f)    => format!("{}", f),
+      MalVal::BigInt(b) if print_readably => format!("{}N", b),
+      MalVal::BigInt(b) => format!("{}", b),
+      MalVal::Ratio(r) => format!("{}/{}", r.numer(), r.denom()),
+      Float(f) if f.is_nan() => String::from("##NaN"),
+      Float(f) if f.is_infinite() => {
+        String::from(if *f > 0.0 { "##Inf" } else { "##-Inf" })
+      },
+      Float(f)    => format!("{:?}", f),
+      Keyword(k)  => format!(":{}", k),
       Str(s)      => {
-        if s.starts_with("\u{29e}") {
-          format!(":{}", &s[2..])
-        } else if print_readably {
+        if print_readably {
           format!("\"{}\"", escape_str(s))
         } else {
           s.clone()
         }
       }
       Sym(s)      => s.clone(),
-      List(l,_)   => pr_seq(&**l, print_readably, "(", ")", " "),
-      Vector(l,_) => pr_seq(&**l, print_readably, "[", "]", " "),
+      List(l,_)   => pr_items(&**l, print_readably, strict, "(", ")", " ")?,
+      Vector(l,_) => pr_items(&**l, print_readably, strict, "[", "]", " ")?,
       Hash(hm,_)  => {
         let l: Vec<MalVal> = hm
           .iter()
-          .flat_map(|(k, v)| { vec![Str(k.to_string()), v.clone()] })
+          .flat_map(|(k, v)| { vec![k.clone(), v.clone()] })
           .collect();
-        pr_seq(&l, print_readably, "{", "}", " ")
+        pr_items(&l, print_readably, strict, "{", "}", " ")?
+      },
+      Set(hs,_)   => pr_items(hs.iter(), print_readably, strict, "#{", "}", " ")?,
+      LazySeq(..) => match self.seq_vec() {
+        Ok(items) => pr_items(&items, print_readably, strict, "(", ")", " ")?,
+        Err(e) if !strict => format!("#<lazy-seq error: {}>", format_error(e)),
+        Err(e) => return Err(e),
       },
-      Func(f,_)   => format!("#<fn {:?}>", f),
+      Tagged(t,v) => format!("#{} {}", t, v.pr(print_readably, strict)?),
+      Regex(r) if print_readably => format!("#\"{}\"", escape_regex(r.re.as_str())),
+      Regex(r)    => r.re.as_str().to_string(),
+      Func(f,_)   => format!("#<fn {}>", f.name),
       MalFunc{ast: a, params: p, ..} => {
         format!("(fn* {} {})", p.pr_str(true), a.pr_str(true))
       },
-      Atom(a)     => format!("(atom {})", a.borrow().pr_str(true)),
-    }
+      Atom(a)     => format!("(atom {})", a.borrow().pr(true, strict)?),
+      Reduced(v)  => format!("#<reduced {}>", v.pr(true, strict)?),
+    })
   }
 }
 
-pub fn pr_seq(seq: &Vec<MalVal>, print_readably: bool,
-              start: &str, end: &str, join: &str) -> String {
-  let strs: Vec<String> = seq
-    .iter()
-    .map(|x| x.pr_str(print_readably))
-    .collect();
-  format!("{}{}{}", start, strs.join(join), end)
+// prints each item of seq, failing if a lazy seq among them fails to
+// realize
+pub fn pr_seq<'a, I>(seq: I, print_readably: bool,
+                     start: &str, end: &str, join: &str) -> Result<String,MalErr>
+  where I: IntoIterator<Item = &'a MalVal> {
+  pr_items(seq, print_readably, true, start, end, join)
+}
+
+fn pr_items<'a, I>(seq: I, print_readably: bool, strict: bool,
+                   start: &str, end: &str, join: &str) -> Result<String,MalErr>
+  where I: IntoIterator<Item = &'a MalVal> {
+  let strs = seq
+    .into_iter()
+    .map(|x| x.pr(print_readably, strict))
+    .collect::<Result<Vec<String>,MalErr>>()?;
+  Ok(format!("{}{}{}", start, strs.join(join), end))
 }
 
 // vim: ts=2:sw=2:expandtab
@@ -1,37 +1,179 @@
-use std::rc::Rc;
+use std::rc::{Rc,Weak};
+use std::cell::{Cell,RefCell};
+use std::fmt;
 use regex::{Regex,Captures};
+use fnv::FnvHashMap;
 
-use types::{MalVal,MalRet,MalErr,error,hash_map};
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector};
-use types::MalErr::ErrString;
+use num_bigint::BigInt;
+use num_traits::Zero;
+use num_rational::BigRational;
+
+use types::{MalVal,MalSet,MalRet,MalErr,ErrKind,error_kind,hash_map,bigint,ratio,keyword,regex};
+use types::MalVal::{Nil,Bool,Float,Str,Sym,List,Vector,Hash,Set,Tagged};
+
+// Source location of a form. Lines and columns are 1-based and columns
+// count chars; end_line/end_col point just past the end of the form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+  pub file: Option<Rc<str>>,
+  pub line: usize,
+  pub col: usize,
+  pub end_line: usize,
+  pub end_col: usize,
+}
+
+impl fmt::Display for Span {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.file {
+      Some(ref file) => write!(f, "{}:{}:{}", file, self.line, self.col),
+      None           => write!(f, "line {}, column {}", self.line, self.col),
+    }
+  }
+}
+
+#[derive(Debug)]
+struct FormSpans {
+  form: Weak<MalVal>,
+  span: Span,
+  items: Vec<Span>,
+}
+
+// Spans of the lists, vectors and hash-maps built by the reader, keyed by
+// the address of the form's meta cell. That Rc is shared by every clone
+// of the form and replaced by with-meta, so it identifies the form
+// without changing MalVal; the Weak guards against the address being
+// reused once the form is gone.
+thread_local! {
+  static SPANS: RefCell<FnvHashMap<usize,FormSpans>> = RefCell::new(FnvHashMap::default());
+  static SPANS_LIMIT: Cell<usize> = const { Cell::new(1024) };
+}
+
+fn meta_cell(form: &MalVal) -> Option<&Rc<MalVal>> {
+  match form {
+    List(_,meta) | Vector(_,meta) | Hash(_,meta) | Set(_,meta) => Some(meta),
+    _ => None,
+  }
+}
+
+fn record_span(form: &MalVal, span: Span, items: Vec<Span>) {
+  let meta = match meta_cell(form) {
+    Some(meta) => meta,
+    None => return,
+  };
+  SPANS.with(|spans| {
+    let mut spans = spans.borrow_mut();
+    let limit = SPANS_LIMIT.with(|l| l.get());
+    if spans.len() >= limit {
+      spans.retain(|_, fs| fs.form.upgrade().is_some());
+      SPANS_LIMIT.with(|l| l.set(std::cmp::max(limit, spans.len() * 2)));
+    }
+    spans.insert(Rc::as_ptr(meta) as usize,
+                 FormSpans{form: Rc::downgrade(meta), span, items});
+  });
+}
+
+fn lookup_span<T, F>(form: &MalVal, f: F) -> Option<T>
+  where F: FnOnce(&FormSpans) -> Option<T> {
+  let meta = meta_cell(form)?;
+  SPANS.with(|spans| {
+    match spans.borrow().get(&(Rc::as_ptr(meta) as usize)) {
+      Some(fs) if fs.form.upgrade().is_some_and(|m| Rc::ptr_eq(&m, meta)) => f(fs),
+      _ => None,
+    }
+  })
+}
+
+// where the reader found a list, vector or hash-map
+pub fn span_of(form: &MalVal) -> Option<Span> {
+  lookup_span(form, |fs| Some(fs.span.clone()))
+}
+
+// where the reader found the idx'th element of a list or vector
+pub fn item_span(form: &MalVal, idx: usize) -> Option<Span> {
+  lookup_span(form, |fs| fs.items.get(idx).cloned())
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+  text: String,
+  start: usize,
+  end: usize,
+}
+
+// the functions that read edn tagged elements: readers maps a tag
+// symbol to a function of the value, and default, unless nil, is called
+// with the tag and value for any other tag
+#[derive(Debug, Clone)]
+struct EdnReaders {
+  readers: MalVal,
+  default: MalVal,
+}
 
 #[derive(Debug, Clone)]
 struct Reader {
-    tokens: Vec<String>,
+    tokens: Vec<Token>,
     pos: usize,
+    src: String,
+    line_starts: Vec<usize>,
+    file: Option<Rc<str>>,
+    // set when reading edn data rather than code
+    edn: Option<EdnReaders>,
 }
 
 impl Reader {
-  fn next(&mut self) -> Result<String,MalErr> {
-    self.pos = self.pos + 1;
+  fn new(src: String, file: Option<&str>) -> Reader {
+    let mut line_starts = vec![0];
+    line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+    Reader {
+      tokens: tokenize(&src),
+      pos: 0,
+      src,
+      line_starts,
+      file: file.map(Rc::from),
+      edn: None,
+    }
+  }
+  fn next(&mut self) -> Result<Token,MalErr> {
+    self.pos += 1;
     Ok(self.tokens.get(self.pos-1)
-         .ok_or(ErrString("underflow".to_string()))?.to_string())
+         .ok_or(MalErr::new(ErrKind::Reader, "underflow"))?.clone())
   }
-  fn peek(&self) -> Result<String,MalErr> {
+  fn peek(&self) -> Result<Token,MalErr> {
     Ok(self.tokens.get(self.pos)
-         .ok_or(ErrString("underflow".to_string()))?.to_string())
+         .ok_or(MalErr::new(ErrKind::Reader, "underflow"))?.clone())
+  }
+  // byte offset just past the last token consumed
+  fn last_end(&self) -> usize {
+    self.tokens[self.pos-1].end
+  }
+  fn location(&self, offset: usize) -> (usize, usize) {
+    let line = match self.line_starts.binary_search(&offset) {
+      Ok(l) => l,
+      Err(l) => l - 1,
+    };
+    (line + 1, self.src[self.line_starts[line]..offset].chars().count() + 1)
+  }
+  fn span(&self, start: usize, end: usize) -> Span {
+    let (line, col) = self.location(start);
+    let (end_line, end_col) = self.location(end);
+    Span{file: self.file.clone(), line, col, end_line, end_col}
+  }
+  fn error_at(&self, msg: &str, offset: usize) -> MalRet {
+    Err(MalErr::new(ErrKind::Reader, msg).at(Some(self.span(offset, offset))))
   }
 }
 
-fn tokenize(str: &str) -> Vec<String> {
+fn tokenize(str: &str) -> Vec<Token> {
   lazy_static! {
-      static ref RE: Regex = Regex::new(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###).unwrap();
+      static ref RE: Regex = Regex::new(r###"[\s,]*(~@|#\{|#_|#"(?:\\.|[^\\"])*"?|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}("`,;)]+)"###).unwrap();
   }
 
   let mut res = vec![];
   for cap in RE.captures_iter(str) {
-    if cap[1].starts_with(";") { continue }
-    res.push(String::from(&cap[1]));
+    if cap[1].starts_with(';') { continue }
+    let tok = cap.get(1).unwrap();
+    res.push(Token{text: String::from(tok.as_str()),
+                   start: tok.start(), end: tok.end()});
   }
   res
 }
@@ -40,31 +182,145 @@ fn unescape_str(s: &str) -> String {
   lazy_static! {
     static ref RE: Regex = Regex::new(r#"\\(.)"#).unwrap();
   }
-  RE.replace_all(&s, |caps: &Captures| {
-    format!("{}", if &caps[1] == "n" { "\n" } else { &caps[1] })
+  RE.replace_all(s, |caps: &Captures| {
+    if &caps[1] == "n" { "\n".to_string() } else { caps[1].to_string() }
   }).to_string()
 }
 
+// the pattern of a #"..." literal: \" is a quote and other escapes are
+// kept for the regex itself
+fn unescape_regex(s: &str) -> String {
+  let mut res = String::new();
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    match (c, chars.clone().next()) {
+      ('\\', Some('"')) => (),
+      ('\\', Some(n)) => {
+        res.push(c);
+        res.push(n);
+        chars.next();
+      },
+      _ => res.push(c),
+    }
+  }
+  res
+}
+
+// edn strings also have the escapes \t, \r, \b, \f and \uXXXX
+fn unescape_edn_str(s: &str) -> Result<String,String> {
+  lazy_static! {
+    static ref RE: Regex = Regex::new(r#"\\(u[0-9a-fA-F]{4}|.)"#).unwrap();
+  }
+  let mut bad = None;
+  let res = RE.replace_all(s, |caps: &Captures| {
+    match &caps[1] {
+      "n" => "\n".to_string(),
+      "t" => "\t".to_string(),
+      "r" => "\r".to_string(),
+      "b" => "\u{8}".to_string(),
+      "f" => "\u{c}".to_string(),
+      "\"" | "\\" => caps[1].to_string(),
+      u if u.len() == 5 => {
+        match char::from_u32(u32::from_str_radix(&u[1..], 16).unwrap()) {
+          Some(c) => c.to_string(),
+          None => { bad = Some(format!("invalid unicode escape '\\{}'", u)); String::new() },
+        }
+      },
+      c => { bad = Some(format!("invalid escape '\\{}' in string", c)); String::new() },
+    }
+  }).to_string();
+  match bad {
+    Some(msg) => Err(msg),
+    None => Ok(res),
+  }
+}
+
+// an edn character, which mal reads as a one-character string; \( and
+// the like tokenize as a lone \ followed by the bracket
+fn read_char(rdr: &mut Reader, token: &str, start: usize) -> MalRet {
+  let name = match &token[1..] {
+    "" => match rdr.peek() {
+      Ok(ref t) if t.start == start + 1 && t.text.chars().count() == 1 => rdr.next()?.text,
+      _ => return rdr.error_at("invalid character '\\'", start),
+    },
+    n => n.to_string(),
+  };
+  let c = match &name[..] {
+    "newline"   => Some('\n'),
+    "return"    => Some('\r'),
+    "space"     => Some(' '),
+    "tab"       => Some('\t'),
+    "backspace" => Some('\u{8}'),
+    "formfeed"  => Some('\u{c}'),
+    n if n.chars().count() == 1 => n.chars().next(),
+    n if n.len() == 5 && n.starts_with('u') => {
+      u32::from_str_radix(&n[1..], 16).ok().and_then(char::from_u32)
+    },
+    _ => None,
+  };
+  match c {
+    Some(c) => Ok(Str(c.to_string())),
+    None    => rdr.error_at(&format!("invalid character '\\{}'", name), start),
+  }
+}
+
 fn read_atom(rdr: &mut Reader) -> MalRet {
   lazy_static! {
-    static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+$").unwrap();
+    static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+N?$").unwrap();
+    static ref RATIO_RE: Regex = Regex::new(r"^(-?[0-9]+)/([0-9]+)$").unwrap();
+    static ref FLOAT_RE: Regex =
+      Regex::new(r"^-?[0-9]+(\.[0-9]*)?([eE][-+]?[0-9]+)?$").unwrap();
   }
-  let token = rdr.next()?;
+  let Token{text: token, start, ..} = rdr.next()?;
   match &token[..] {
     "nil"   => Ok(Nil),
     "false" => Ok(Bool(false)),
     "true"  => Ok(Bool(true)),
+    "##Inf"  => Ok(Float(f64::INFINITY)),
+    "##-Inf" => Ok(Float(f64::NEG_INFINITY)),
+    "##NaN"  => Ok(Float(f64::NAN)),
     _       => {
       if INT_RE.is_match(&token) {
-        Ok(Int(token.parse().unwrap()))
-      } else if token.starts_with("\"") {
-        if token.ends_with("\"") {
+        // an N suffix keeps a BigInt even for a value an Int could hold
+        match token.strip_suffix('N') {
+          Some(digits) => Ok(MalVal::BigInt(Rc::new(digits.parse().unwrap()))),
+          None         => Ok(bigint(token.parse().unwrap())),
+        }
+      } else if let Some(caps) = RATIO_RE.captures(&token) {
+        let denom: BigInt = caps[2].parse().unwrap();
+        if denom.is_zero() {
+          rdr.error_at("divide by zero in ratio", start)
+        } else {
+          Ok(ratio(BigRational::new(caps[1].parse().unwrap(), denom)))
+        }
+      } else if FLOAT_RE.is_match(&token) {
+        Ok(Float(token.parse().unwrap()))
+      } else if token.starts_with("#\"") {
+        if token.len() > 2 && token.ends_with('"') {
+          regex(&unescape_regex(&token[2..token.len()-1]))
+            .map_err(|e| MalErr::new(ErrKind::Reader, &e.msg).at(Some(rdr.span(start, start))))
+        } else {
+          rdr.error_at("expected '\"', got EOF", start)
+        }
+      } else if token.starts_with('"') {
+        if token.len() > 1 && token.ends_with('"') && rdr.edn.is_some() {
+          match unescape_edn_str(&token[1..token.len()-1]) {
+            Ok(s) => Ok(Str(s)),
+            Err(msg) => rdr.error_at(&msg, start),
+          }
+        } else if token.len() > 1 && token.ends_with('"') {
           Ok(Str(unescape_str(&token[1..token.len()-1])))
         } else {
-          error("expected '\"', got EOF")
+          rdr.error_at("expected '\"', got EOF", start)
         }
-      } else if token.starts_with(":") {
-        Ok(Str(format!("\u{29e}{}", &token[1..])))
+      } else if token.starts_with('\\') && rdr.edn.is_some() {
+        read_char(rdr, &token, start)
+      } else if token.strip_suffix('M').is_some_and(|t| FLOAT_RE.is_match(t))
+                && rdr.edn.is_some() {
+        // mal has no decimal type, so an edn decimal reads as a float
+        Ok(Float(token[..token.len()-1].parse().unwrap()))
+      } else if let Some(kw) = token.strip_prefix(':') {
+        Ok(keyword(kw))
       } else {
         Ok(Sym(token.to_string()))
       }
@@ -72,71 +328,200 @@ fn read_atom(rdr: &mut Reader) -> MalRet {
   }
 }
 
-fn read_seq(rdr: &mut Reader, end: &str) -> MalRet {
+// reads a list, vector, hash-map or set, given the token that opens it
+fn read_seq(rdr: &mut Reader, open: &str) -> MalRet {
+  let end = match open {
+    "(" => ")",
+    "[" => "]",
+    _   => "}",
+  };
   let mut seq : Vec<MalVal> = vec![];
-  rdr.next()?;
+  let mut items = vec![];
+  let start = rdr.next()?.start;
   loop {
+    skip_discarded(rdr)?;
     let token = match rdr.peek() {
       Ok(t) => t,
-      Err(_) => return error(&format!("expected '{}', got EOF", end))
+      Err(_) => return rdr.error_at(&format!("expected '{}', got EOF", end), start),
     };
-    if token == end { break }
-    seq.push(read_form(rdr)?)
+    if token.text == end { break }
+    seq.push(read_form(rdr)?);
+    items.push(rdr.span(token.start, rdr.last_end()));
   }
   let _ = rdr.next();
-  match end {
-    ")" => Ok(list!(seq)),
-    "]" => Ok(vector!(seq)),
-    "}" => hash_map(seq),
-    _   => error("read_seq unknown end value"),
+  let form = match open {
+    "(" => list!(seq),
+    "[" => vector!(seq),
+    "{" => {
+      let len = seq.len();
+      match hash_map(seq)? {
+        Hash(ref hm,_) if hm.len() * 2 != len => {
+          return rdr.error_at("duplicate key in map literal", start)
+        },
+        hm => hm,
+      }
+    },
+    "#{" => {
+      let len = seq.len();
+      let set: MalSet = seq.into_iter().collect();
+      if set.len() != len {
+        return rdr.error_at("duplicate element in set literal", start)
+      }
+      Set(Rc::new(set),Rc::new(Nil))
+    },
+    _   => return error_kind(ErrKind::Reader, "read_seq unknown start value"),
+  };
+  record_span(&form, rdr.span(start, rdr.last_end()), items);
+  Ok(form)
+}
+
+// reads the form following a reader macro character and wraps it as
+// (sym form), or (with-meta form meta) for ^
+fn read_macro(rdr: &mut Reader, sym: &str) -> MalRet {
+  let macro_tok = rdr.next()?;
+  let mut items = vec![rdr.span(macro_tok.start, macro_tok.end)];
+  let mut args = vec![];
+  let count = if sym == "with-meta" { 2 } else { 1 };
+  for _ in 0..count {
+    let start = rdr.peek()?.start;
+    args.push(read_form(rdr)?);
+    items.push(rdr.span(start, rdr.last_end()));
+  }
+  if sym == "with-meta" {
+    args.swap(0, 1);
+    items.swap(1, 2);
+  }
+  args.insert(0, Sym(sym.to_string()));
+  let form = list!(args);
+  record_span(&form, rdr.span(macro_tok.start, rdr.last_end()), items);
+  Ok(form)
+}
+
+// skips any forms preceded by #_
+fn skip_discarded(rdr: &mut Reader) -> Result<(),MalErr> {
+  while rdr.peek().is_ok_and(|t| t.text == "#_") {
+    let tok = rdr.next()?;
+    if rdr.peek().is_err() {
+      return rdr.error_at("expected a form after #_, got EOF", tok.start).map(|_| ());
+    }
+    read_form(rdr)?;
+  }
+  Ok(())
+}
+
+// #inst and #uuid elements are checked and kept as tagged values
+fn read_builtin_tag(tag: &str, v: MalVal) -> Option<Result<MalVal,String>> {
+  lazy_static! {
+    static ref INST_RE: Regex = Regex::new(
+      r"^\d{4}(-\d{2}(-\d{2}(T\d{2}(:\d{2}(:\d{2}(\.\d+)?)?)?)?)?)?(Z|[-+]\d{2}:\d{2})?$").unwrap();
+    static ref UUID_RE: Regex = Regex::new(
+      r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$").unwrap();
+  }
+  let re: &Regex = match tag {
+    "inst" => &INST_RE,
+    "uuid" => &UUID_RE,
+    _ => return None,
+  };
+  match v {
+    Str(ref s) if re.is_match(s) => Some(Ok(Tagged(Rc::from(tag), Rc::new(v.clone())))),
+    _ => Some(Err(format!("invalid #{} value {}", tag, v.pr_str(true)))),
+  }
+}
+
+// reads #tag value, passing the value to the reader function for tag
+fn read_tagged(rdr: &mut Reader) -> MalRet {
+  let tok = rdr.next()?;
+  let tag = &tok.text[1..];
+  if !tag.starts_with(|c: char| c.is_alphabetic()) {
+    return rdr.error_at(&format!("invalid edn tag '{}'", tok.text), tok.start);
   }
+  let v = read_form(rdr)?;
+  let edn = rdr.edn.clone().unwrap();
+  let reader = match edn.readers {
+    Hash(ref hm,_) => hm.get(&Sym(tag.to_string())).cloned(),
+    _ => None,
+  };
+  let res = match (reader, read_builtin_tag(tag, v.clone()), edn.default) {
+    (Some(f), _, _) => f.apply(vec![v]),
+    (None, Some(Ok(v)), _) => Ok(v),
+    (None, Some(Err(msg)), _) => return rdr.error_at(&msg, tok.start),
+    (None, None, Nil) => {
+      return rdr.error_at(&format!("no reader function for tag {}", tag), tok.start)
+    },
+    (None, None, f) => f.apply(vec![Sym(tag.to_string()), v]),
+  };
+  res.map_err(|e| e.at(Some(rdr.span(tok.start, rdr.last_end()))))
 }
 
 fn read_form(rdr: &mut Reader) -> MalRet {
+  skip_discarded(rdr)?;
   let token = rdr.peek()?;
-  match &token[..] {
-    "'"  => {
-      let _ = rdr.next();
-      Ok(list![Sym("quote".to_string()), read_form(rdr)?])
-    },
-    "`"  => {
-      let _ = rdr.next();
-      Ok(list![Sym("quasiquote".to_string()), read_form(rdr)?])
-    },
-    "~"  => {
-      let _ = rdr.next();
-      Ok(list![Sym("unquote".to_string()), read_form(rdr)?])
-    },
-    "~@"  => {
-      let _ = rdr.next();
-      Ok(list![Sym("splice-unquote".to_string()), read_form(rdr)?])
-    },
-    "^"  => {
-      let _ = rdr.next();
-      let meta = read_form(rdr)?;
-      Ok(list![Sym("with-meta".to_string()), read_form(rdr)?, meta])
-    },
-    "@"  => {
-      let _ = rdr.next();
-      Ok(list![Sym("deref".to_string()), read_form(rdr)?])
-    },
-    ")"  => error("unexpected ')'"),
-    "("  => read_seq(rdr, ")"),
-    "]"  => error("unexpected ']'"),
-    "["  => read_seq(rdr, "]"),
-    "}"  => error("unexpected '}'"),
-    "{"  => read_seq(rdr, "}"),
+  if rdr.edn.is_some() {
+    match &token.text[..] {
+      "'" | "`" | "~" | "~@" | "^" | "@" => {
+        return rdr.error_at(&format!("'{}' is not valid in edn", token.text), token.start)
+      },
+      t if t.starts_with("#\"") => {
+        return rdr.error_at("regex literals are not valid in edn", token.start)
+      },
+      t if t.starts_with('#') && !t.starts_with("##") && t != "#{" => {
+        return read_tagged(rdr)
+      },
+      _ => (),
+    }
+  }
+  match &token.text[..] {
+    "'"  => read_macro(rdr, "quote"),
+    "`"  => read_macro(rdr, "quasiquote"),
+    "~"  => read_macro(rdr, "unquote"),
+    "~@" => read_macro(rdr, "splice-unquote"),
+    "^"  => read_macro(rdr, "with-meta"),
+    "@"  => read_macro(rdr, "deref"),
+    ")"  => rdr.error_at("unexpected ')'", token.start),
+    "("  => read_seq(rdr, "("),
+    "]"  => rdr.error_at("unexpected ']'", token.start),
+    "["  => read_seq(rdr, "["),
+    "}"  => rdr.error_at("unexpected '}'", token.start),
+    "{"  => read_seq(rdr, "{"),
+    "#{" => read_seq(rdr, "#{"),
     _    => read_atom(rdr),
   }
 }
 
 pub fn read_str(str: String) -> MalRet {
-  let tokens = tokenize(&str);
-  //println!("tokens: {:?}", tokens);
-  if tokens.len() == 0 {
-    return error("no input");
+  let mut rdr = Reader::new(str, None);
+  //println!("tokens: {:?}", rdr.tokens);
+  if rdr.tokens.is_empty() {
+    return error_kind(ErrKind::Reader, "no input");
+  }
+  read_form(&mut rdr)
+}
+
+// reads the first edn form in str, or nil if there is none; readers and
+// default handle tagged elements
+pub fn read_edn(str: String, readers: MalVal, default: MalVal) -> MalRet {
+  let mut rdr = Reader::new(str, None);
+  rdr.edn = Some(EdnReaders{readers, default});
+  skip_discarded(&mut rdr)?;
+  if rdr.pos == rdr.tokens.len() {
+    return Ok(Nil);
+  }
+  read_form(&mut rdr)
+}
+
+// reads every form in str, recording file, if any, as the source of
+// their spans
+pub fn read_all(str: String, file: Option<&str>) -> Result<Vec<MalVal>,MalErr> {
+  let mut rdr = Reader::new(str, file);
+  let mut forms = vec![];
+  loop {
+    skip_discarded(&mut rdr)?;
+    if rdr.pos == rdr.tokens.len() {
+      break;
+    }
+    forms.push(read_form(&mut rdr)?);
   }
-  read_form(&mut Reader { pos: 0, tokens: tokens })
+  Ok(forms)
 }
 
 // vim: ts=2:sw=2:expandtab
@@ -0,0 +1,121 @@
+use std::rc::Rc;
+use regex::Captures;
+
+use types::{MalVal,MalRegex,MalRet,MalErr,ErrKind,error_kind,regex,keyword_map};
+use types::MalVal::{Nil,Str,List,Vector,Regex,Func,MalFunc};
+use core::str_arg;
+
+fn regex_arg(name: &str, v: &MalVal) -> Result<Rc<MalRegex>,MalErr> {
+  match v {
+    Regex(r) => Ok(r.clone()),
+    _ => Err(MalErr::new(ErrKind::Type,
+                         &format!("{}: expected a regex, got {}", name, v.pr_str(true)))),
+  }
+}
+
+// every group of a match, the whole match first, with nil for groups
+// that took no part in it
+fn groups(caps: &Captures) -> MalVal {
+  vector!(caps.iter()
+              .map(|m| m.map_or(Nil, |m| Str(m.as_str().to_string())))
+              .collect::<Vec<MalVal>>())
+}
+
+// a match as Clojure returns it: the matched string when the regex has
+// no groups, else a vector of groups; with named groups it is instead a
+// map of each name, as a keyword, to its group
+fn match_val(r: &MalRegex, caps: &Captures) -> MalVal {
+  if r.re.capture_names().any(|n| n.is_some()) {
+    keyword_map(r.re.capture_names().flatten().map(|n| {
+      (n, caps.name(n).map_or(Nil, |m| Str(m.as_str().to_string())))
+    }).collect())
+  } else if caps.len() == 1 {
+    Str(caps[0].to_string())
+  } else {
+    groups(caps)
+  }
+}
+
+pub fn re_pattern(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Regex(_) => Ok(a[0].clone()),
+    Str(ref s) => regex(s),
+    _ => error_kind(ErrKind::Type, &format!("re-pattern: expected a string, got {}",
+                                            a[0].pr_str(true))),
+  }
+}
+
+// the first match of re in s, or nil
+pub fn re_find(a: &[MalVal]) -> MalRet {
+  let r = regex_arg("re-find", &a[0])?;
+  let s = str_arg("re-find", &a[1])?;
+  Ok(r.re.captures(s).map_or(Nil, |caps| match_val(&r, &caps)))
+}
+
+// the match of re against the whole of s, or nil
+pub fn re_matches(a: &[MalVal]) -> MalRet {
+  let r = regex_arg("re-matches", &a[0])?;
+  let s = str_arg("re-matches", &a[1])?;
+  Ok(r.whole().captures(s).map_or(Nil, |caps| match_val(&r, &caps)))
+}
+
+// every match of re in s, or nil when there are none
+pub fn re_seq(a: &[MalVal]) -> MalRet {
+  let r = regex_arg("re-seq", &a[0])?;
+  let s = str_arg("re-seq", &a[1])?;
+  let ms: Vec<MalVal> = r.re.captures_iter(s).map(|caps| match_val(&r, &caps)).collect();
+  Ok(if ms.is_empty() { Nil } else { list!(ms) })
+}
+
+// the groups of the first match of re in s as a vector, whatever the
+// regex's groups are called, or nil
+pub fn re_groups(a: &[MalVal]) -> MalRet {
+  let r = regex_arg("re-groups", &a[0])?;
+  let s = str_arg("re-groups", &a[1])?;
+  Ok(r.re.captures(s).map_or(Nil, |caps| groups(&caps)))
+}
+
+// (replace s match replacement) replaces every match in s: a string
+// match is replaced literally, and a regex match by a string, in which
+// $1 or ${name} is a group, or by calling a function on the match
+pub fn replace(a: &[MalVal]) -> MalRet {
+  let s = str_arg("replace", &a[0])?;
+  match (&a[1], &a[2]) {
+    (Str(m), Str(rep)) => Ok(Str(s.replace(&m[..], rep))),
+    (Regex(r), Str(rep)) => Ok(Str(r.re.replace_all(s, &rep[..]).into_owned())),
+    (Regex(r), f @ Func(..)) | (Regex(r), f @ MalFunc{..}) => {
+      let mut err = None;
+      let res = r.re.replace_all(s, |caps: &Captures| {
+        if err.is_some() {
+          return String::new();
+        }
+        match f.apply(vec![match_val(r, caps)]) {
+          Ok(Str(rep)) => rep,
+          Ok(v) => {
+            err = Some(MalErr::new(ErrKind::Type,
+                                   &format!("replace: expected the function to return a string, got {}",
+                                            v.pr_str(true))));
+            String::new()
+          },
+          Err(e) => {
+            err = Some(e);
+            String::new()
+          },
+        }
+      }).into_owned();
+      match err {
+        Some(e) => Err(e),
+        None    => Ok(Str(res)),
+      }
+    },
+    (Str(_), rep) | (Regex(_), rep) => {
+      error_kind(ErrKind::Type, &format!("replace: invalid replacement {}", rep.pr_str(true)))
+    },
+    (m, _) => {
+      error_kind(ErrKind::Type, &format!("replace: expected a string or regex, got {}",
+                                         m.pr_str(true)))
+    },
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -0,0 +1,650 @@
+use std::rc::Rc;
+use std::fmt;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use serde::ser::{self,Serialize,SerializeSeq,SerializeMap};
+use serde::de::{self,Deserialize,Visitor,SeqAccess,MapAccess,EnumAccess,VariantAccess,
+                IntoDeserializer,DeserializeSeed};
+
+use types::{MalVal,MalMap,MalErr,ErrKind,bigint,keyword};
+use types::MalVal::{Nil,Bool,Int,Float,Ratio,Str,Keyword,Sym,List,Vector,Hash,Set,LazySeq,
+                    Tagged,Regex,Func,MalFunc,Atom,Reduced};
+
+// serde support: MalVal can be written to and read from any serde
+// format, and to_value/from_value use MalVal itself as a format, with
+// structs as maps with keyword keys and enum variants as vectors tagged
+// with a keyword (or just the keyword, for a unit variant)
+
+impl ser::Error for MalErr {
+  fn custom<T: fmt::Display>(msg: T) -> MalErr {
+    MalErr::new(ErrKind::Type, &msg.to_string())
+  }
+}
+
+impl de::Error for MalErr {
+  fn custom<T: fmt::Display>(msg: T) -> MalErr {
+    MalErr::new(ErrKind::Type, &msg.to_string())
+  }
+}
+
+fn unserializable(v: &MalVal) -> String {
+  format!("cannot serialize {}", v.pr_str(true))
+}
+
+// keywords, symbols and regexes are written as their names or patterns,
+// tagged elements as just their value, and big integers only where they
+// fit in 128 bits
+impl Serialize for MalVal {
+  fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok,S::Error> {
+    match self {
+      Nil      => s.serialize_unit(),
+      Bool(b)  => s.serialize_bool(*b),
+      Int(i)   => s.serialize_i64(*i),
+      Float(f) => s.serialize_f64(*f),
+      MalVal::BigInt(b) => match (b.to_i64(), b.to_u64(), b.to_i128(), b.to_u128()) {
+        (Some(i), _, _, _) => s.serialize_i64(i),
+        (_, Some(u), _, _) => s.serialize_u64(u),
+        (_, _, Some(i), _) => s.serialize_i128(i),
+        (_, _, _, Some(u)) => s.serialize_u128(u),
+        _ => Err(ser::Error::custom(unserializable(self))),
+      },
+      Ratio(r) => s.serialize_f64(r.to_f64().unwrap_or(f64::NAN)),
+      Str(st)  => s.serialize_str(st),
+      Keyword(k) => s.serialize_str(k),
+      Sym(st)  => s.serialize_str(st),
+      Regex(r) => s.serialize_str(r.re.as_str()),
+      List(..) | Vector(..) | Set(..) | LazySeq(..) => {
+        let items = self.seq_vec().map_err(|e| ser::Error::custom(e.msg.clone()))?;
+        let mut seq = s.serialize_seq(Some(items.len()))?;
+        for x in items.iter() {
+          seq.serialize_element(x)?;
+        }
+        seq.end()
+      },
+      Hash(hm,_) => {
+        let mut map = s.serialize_map(Some(hm.len()))?;
+        for (k, v) in hm.iter() {
+          map.serialize_entry(k, v)?;
+        }
+        map.end()
+      },
+      Tagged(_,v) => v.serialize(s),
+      Atom(a) => a.borrow().serialize(s),
+      Func(..) | MalFunc{..} | Reduced(_) => Err(ser::Error::custom(unserializable(self))),
+    }
+  }
+}
+
+struct MalValVisitor;
+
+// sequences are read as vectors and maps keep the keys they are given
+impl<'de> Visitor<'de> for MalValVisitor {
+  type Value = MalVal;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a mal value")
+  }
+
+  fn visit_bool<E>(self, b: bool) -> Result<MalVal,E> { Ok(Bool(b)) }
+  fn visit_i64<E>(self, i: i64) -> Result<MalVal,E> { Ok(Int(i)) }
+  fn visit_i128<E>(self, i: i128) -> Result<MalVal,E> { Ok(bigint(BigInt::from(i))) }
+  fn visit_u64<E>(self, u: u64) -> Result<MalVal,E> { Ok(bigint(BigInt::from(u))) }
+  fn visit_u128<E>(self, u: u128) -> Result<MalVal,E> { Ok(bigint(BigInt::from(u))) }
+  fn visit_f64<E>(self, f: f64) -> Result<MalVal,E> { Ok(Float(f)) }
+  fn visit_str<E>(self, s: &str) -> Result<MalVal,E> { Ok(Str(s.to_string())) }
+  fn visit_string<E>(self, s: String) -> Result<MalVal,E> { Ok(Str(s)) }
+  fn visit_unit<E>(self) -> Result<MalVal,E> { Ok(Nil) }
+  fn visit_none<E>(self) -> Result<MalVal,E> { Ok(Nil) }
+
+  fn visit_bytes<E>(self, b: &[u8]) -> Result<MalVal,E> {
+    Ok(vector!(b.iter().map(|x| Int(*x as i64)).collect::<Vec<MalVal>>()))
+  }
+
+  fn visit_some<D: de::Deserializer<'de>>(self, d: D) -> Result<MalVal,D::Error> {
+    MalVal::deserialize(d)
+  }
+
+  fn visit_newtype_struct<D: de::Deserializer<'de>>(self, d: D) -> Result<MalVal,D::Error> {
+    MalVal::deserialize(d)
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MalVal,A::Error> {
+    let mut items = vec![];
+    while let Some(x) = seq.next_element()? {
+      items.push(x);
+    }
+    Ok(vector!(items))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MalVal,A::Error> {
+    let mut hm = MalMap::default();
+    while let Some((k, v)) = map.next_entry()? {
+      hm.insert(k, v);
+    }
+    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+  }
+}
+
+impl<'de> Deserialize<'de> for MalVal {
+  fn deserialize<D: de::Deserializer<'de>>(d: D) -> Result<MalVal,D::Error> {
+    d.deserialize_any(MalValVisitor)
+  }
+}
+
+// serializes v into a MalVal
+pub fn to_value<T: Serialize + ?Sized>(v: &T) -> Result<MalVal,MalErr> {
+  v.serialize(Serializer)
+}
+
+// deserializes a T from v
+pub fn from_value<T: de::DeserializeOwned>(v: &MalVal) -> Result<T,MalErr> {
+  T::deserialize(Deserializer(v))
+}
+
+// the serde data format that writes MalVals
+pub struct Serializer;
+
+pub struct SeqSerializer {
+  items: Vec<MalVal>,
+  // the variant keyword of a tuple variant
+  tag: Option<MalVal>,
+}
+
+pub struct MapSerializer {
+  map: MalMap,
+  key: Option<MalVal>,
+  // the variant keyword of a struct variant
+  tag: Option<MalVal>,
+}
+
+fn tagged(tag: Option<MalVal>, v: MalVal) -> MalVal {
+  match tag {
+    Some(t) => vector![t, v],
+    None    => v,
+  }
+}
+
+impl ser::Serializer for Serializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+  type SerializeSeq = SeqSerializer;
+  type SerializeTuple = SeqSerializer;
+  type SerializeTupleStruct = SeqSerializer;
+  type SerializeTupleVariant = SeqSerializer;
+  type SerializeMap = MapSerializer;
+  type SerializeStruct = MapSerializer;
+  type SerializeStructVariant = MapSerializer;
+
+  fn serialize_bool(self, v: bool) -> Result<MalVal,MalErr> { Ok(Bool(v)) }
+  fn serialize_i8(self, v: i8) -> Result<MalVal,MalErr> { Ok(Int(v as i64)) }
+  fn serialize_i16(self, v: i16) -> Result<MalVal,MalErr> { Ok(Int(v as i64)) }
+  fn serialize_i32(self, v: i32) -> Result<MalVal,MalErr> { Ok(Int(v as i64)) }
+  fn serialize_i64(self, v: i64) -> Result<MalVal,MalErr> { Ok(Int(v)) }
+  fn serialize_i128(self, v: i128) -> Result<MalVal,MalErr> { Ok(bigint(BigInt::from(v))) }
+  fn serialize_u8(self, v: u8) -> Result<MalVal,MalErr> { Ok(Int(v as i64)) }
+  fn serialize_u16(self, v: u16) -> Result<MalVal,MalErr> { Ok(Int(v as i64)) }
+  fn serialize_u32(self, v: u32) -> Result<MalVal,MalErr> { Ok(Int(v as i64)) }
+  fn serialize_u64(self, v: u64) -> Result<MalVal,MalErr> { Ok(bigint(BigInt::from(v))) }
+  fn serialize_u128(self, v: u128) -> Result<MalVal,MalErr> { Ok(bigint(BigInt::from(v))) }
+  fn serialize_f32(self, v: f32) -> Result<MalVal,MalErr> { Ok(Float(v as f64)) }
+  fn serialize_f64(self, v: f64) -> Result<MalVal,MalErr> { Ok(Float(v)) }
+  fn serialize_char(self, v: char) -> Result<MalVal,MalErr> { Ok(Str(v.to_string())) }
+  fn serialize_str(self, v: &str) -> Result<MalVal,MalErr> { Ok(Str(v.to_string())) }
+  fn serialize_none(self) -> Result<MalVal,MalErr> { Ok(Nil) }
+  fn serialize_unit(self) -> Result<MalVal,MalErr> { Ok(Nil) }
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<MalVal,MalErr> { Ok(Nil) }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<MalVal,MalErr> {
+    Ok(vector!(v.iter().map(|x| Int(*x as i64)).collect::<Vec<MalVal>>()))
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, v: &T) -> Result<MalVal,MalErr> {
+    v.serialize(self)
+  }
+
+  fn serialize_unit_variant(self, _name: &'static str, _index: u32,
+                            variant: &'static str) -> Result<MalVal,MalErr> {
+    Ok(keyword(variant))
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str,
+                                                     v: &T) -> Result<MalVal,MalErr> {
+    v.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str,
+                                                      _index: u32, variant: &'static str,
+                                                      v: &T) -> Result<MalVal,MalErr> {
+    Ok(vector![keyword(variant), v.serialize(self)?])
+  }
+
+  fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer,MalErr> {
+    Ok(SeqSerializer{items: Vec::with_capacity(len.unwrap_or(0)), tag: None})
+  }
+
+  fn serialize_tuple(self, len: usize) -> Result<SeqSerializer,MalErr> {
+    self.serialize_seq(Some(len))
+  }
+
+  fn serialize_tuple_struct(self, _name: &'static str,
+                            len: usize) -> Result<SeqSerializer,MalErr> {
+    self.serialize_seq(Some(len))
+  }
+
+  fn serialize_tuple_variant(self, _name: &'static str, _index: u32,
+                             variant: &'static str,
+                             len: usize) -> Result<SeqSerializer,MalErr> {
+    let mut items = Vec::with_capacity(len + 1);
+    items.push(keyword(variant));
+    Ok(SeqSerializer{items, tag: None})
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer,MalErr> {
+    Ok(MapSerializer{map: MalMap::default(), key: None, tag: None})
+  }
+
+  fn serialize_struct(self, _name: &'static str,
+                      _len: usize) -> Result<MapSerializer,MalErr> {
+    self.serialize_map(None)
+  }
+
+  fn serialize_struct_variant(self, _name: &'static str, _index: u32,
+                              variant: &'static str,
+                              _len: usize) -> Result<MapSerializer,MalErr> {
+    Ok(MapSerializer{map: MalMap::default(), key: None, tag: Some(keyword(variant))})
+  }
+}
+
+impl SerializeSeq for SeqSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(),MalErr> {
+    self.items.push(to_value(v)?);
+    Ok(())
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> {
+    Ok(tagged(self.tag, vector!(self.items)))
+  }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(),MalErr> {
+    SerializeSeq::serialize_element(self, v)
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> { SerializeSeq::end(self) }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(),MalErr> {
+    SerializeSeq::serialize_element(self, v)
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> { SerializeSeq::end(self) }
+}
+
+// the fields follow the variant keyword in the same vector
+impl ser::SerializeTupleVariant for SeqSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(),MalErr> {
+    SerializeSeq::serialize_element(self, v)
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> { SerializeSeq::end(self) }
+}
+
+impl SerializeMap for MapSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, k: &T) -> Result<(),MalErr> {
+    self.key = Some(to_value(k)?);
+    Ok(())
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(),MalErr> {
+    let k = self.key.take()
+      .ok_or_else(|| MalErr::new(ErrKind::Type, "map value serialized before its key"))?;
+    self.map.insert(k, to_value(v)?);
+    Ok(())
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> {
+    Ok(tagged(self.tag, Hash(Rc::new(self.map), Rc::new(Nil))))
+  }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, field: &'static str,
+                                            v: &T) -> Result<(),MalErr> {
+    self.map.insert(keyword(field), to_value(v)?);
+    Ok(())
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> { SerializeMap::end(self) }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+  type Ok = MalVal;
+  type Error = MalErr;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, field: &'static str,
+                                            v: &T) -> Result<(),MalErr> {
+    ser::SerializeStruct::serialize_field(self, field, v)
+  }
+
+  fn end(self) -> Result<MalVal,MalErr> { SerializeMap::end(self) }
+}
+
+// the serde data format that reads MalVals; keywords and symbols read
+// as strings, so they can name struct fields and enum variants
+pub struct Deserializer<'a>(&'a MalVal);
+
+fn undeserializable(v: &MalVal, what: &str) -> MalErr {
+  MalErr::new(ErrKind::Type, &format!("expected {}, got {}", what, v.pr_str(true)))
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Deserializer<'a> {
+  type Error = MalErr;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,MalErr> {
+    match self.0 {
+      Nil      => visitor.visit_unit(),
+      Bool(b)  => visitor.visit_bool(*b),
+      Int(i)   => visitor.visit_i64(*i),
+      Float(f) => visitor.visit_f64(*f),
+      MalVal::BigInt(b) => match (b.to_i64(), b.to_u64(), b.to_i128(), b.to_u128()) {
+        (Some(i), _, _, _) => visitor.visit_i64(i),
+        (_, Some(u), _, _) => visitor.visit_u64(u),
+        (_, _, Some(i), _) => visitor.visit_i128(i),
+        (_, _, _, Some(u)) => visitor.visit_u128(u),
+        _ => Err(undeserializable(self.0, "an integer that fits in 128 bits")),
+      },
+      Ratio(r) => visitor.visit_f64(r.to_f64().unwrap_or(f64::NAN)),
+      Str(s)   => visitor.visit_str(s),
+      Keyword(k) => visitor.visit_str(k),
+      Sym(s)   => visitor.visit_str(s),
+      Regex(r) => visitor.visit_str(r.re.as_str()),
+      List(..) | Vector(..) | Set(..) | LazySeq(..) => {
+        let items = self.0.seq_vec()?;
+        visitor.visit_seq(SeqDeserializer{items: items.into_iter()})
+      },
+      Hash(hm,_) => {
+        let entries: Vec<(MalVal,MalVal)> = hm.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        visitor.visit_map(MapDeserializer{entries: entries.into_iter(), value: None})
+      },
+      Tagged(_,v) => Deserializer(v).deserialize_any(visitor),
+      Atom(a) => Deserializer(&a.borrow()).deserialize_any(visitor),
+      Func(..) | MalFunc{..} | Reduced(_) => Err(undeserializable(self.0, "data")),
+    }
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,MalErr> {
+    match self.0 {
+      Nil => visitor.visit_none(),
+      _   => visitor.visit_some(self),
+    }
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str,
+                                                 visitor: V) -> Result<V::Value,MalErr> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  // a unit variant is a keyword (or a string, or a vector holding just
+  // the keyword) and any other variant is a vector of the keyword and
+  // its fields
+  fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                                       _variants: &'static [&'static str],
+                                       visitor: V) -> Result<V::Value,MalErr> {
+    match self.0 {
+      Keyword(_) | Str(_) => visitor.visit_enum(EnumDeserializer{tag: self.0.clone(), fields: vec![]}),
+      List(..) | Vector(..) if self.0.first_rest()?.is_some() => {
+        let mut items = self.0.seq_vec()?;
+        let fields = items.split_off(1);
+        visitor.visit_enum(EnumDeserializer{tag: items.remove(0), fields})
+      },
+      _ => Err(undeserializable(self.0, "an enum variant keyword or tagged vector")),
+    }
+  }
+
+  serde::forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+    identifier ignored_any
+  }
+}
+
+struct SeqDeserializer {
+  items: ::std::vec::IntoIter<MalVal>,
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+  type Error = MalErr;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+      -> Result<Option<T::Value>,MalErr> {
+    match self.items.next() {
+      Some(x) => seed.deserialize(Deserializer(&x)).map(Some),
+      None    => Ok(None),
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.items.len())
+  }
+}
+
+// errors in a value are prefixed with its key
+struct MapDeserializer {
+  entries: ::std::vec::IntoIter<(MalVal,MalVal)>,
+  value: Option<(MalVal,MalVal)>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+  type Error = MalErr;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+      -> Result<Option<K::Value>,MalErr> {
+    match self.entries.next() {
+      Some((k, v)) => {
+        let key = seed.deserialize(Deserializer(&k)).map(Some);
+        self.value = Some((k, v));
+        key
+      },
+      None => Ok(None),
+    }
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value,MalErr> {
+    match self.value.take() {
+      Some((k, v)) => seed.deserialize(Deserializer(&v)).map_err(|mut e| {
+        e.msg = format!("{}: {}", k.pr_str(true), e.msg);
+        e
+      }),
+      None    => Err(MalErr::new(ErrKind::Type, "map value read before its key")),
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.entries.len())
+  }
+}
+
+struct EnumDeserializer {
+  tag: MalVal,
+  fields: Vec<MalVal>,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+  type Error = MalErr;
+  type Variant = EnumDeserializer;
+
+  fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V)
+      -> Result<(V::Value,EnumDeserializer),MalErr> {
+    let name = match self.tag {
+      Keyword(ref k) => k.to_string(),
+      Str(ref s) => s.clone(),
+      _ => return Err(undeserializable(&self.tag, "an enum variant keyword")),
+    };
+    let variant = seed.deserialize(name.into_deserializer())?;
+    Ok((variant, self))
+  }
+}
+
+impl EnumDeserializer {
+  fn only_field(&self, what: &str) -> Result<&MalVal,MalErr> {
+    match self.fields.len() {
+      1 => Ok(&self.fields[0]),
+      n => Err(MalErr::new(ErrKind::Type,
+                           &format!("expected {} after variant {}, got {} values",
+                                    what, self.tag.pr_str(true), n))),
+    }
+  }
+}
+
+impl<'de> VariantAccess<'de> for EnumDeserializer {
+  type Error = MalErr;
+
+  fn unit_variant(self) -> Result<(),MalErr> {
+    match self.fields.len() {
+      0 => Ok(()),
+      n => Err(MalErr::new(ErrKind::Type,
+                           &format!("expected no values after unit variant {}, got {}",
+                                    self.tag.pr_str(true), n))),
+    }
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value,MalErr> {
+    seed.deserialize(Deserializer(self.only_field("one value")?))
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value,MalErr> {
+    visitor.visit_seq(SeqDeserializer{items: self.fields.into_iter()})
+  }
+
+  fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str],
+                                     visitor: V) -> Result<V::Value,MalErr> {
+    de::Deserializer::deserialize_any(Deserializer(self.only_field("a map")?), visitor)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use super::*;
+  use types::keyword_map;
+
+  #[derive(Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+  struct Point {
+    x: i64,
+    y: i64,
+  }
+
+  #[derive(Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+  enum Shape {
+    Dot,
+    Circle(f64),
+    Pair(i64, i64),
+    Rect { w: u16, h: u16 },
+  }
+
+  fn read(src: &str) -> MalVal {
+    ::reader::read_str(src.to_string()).unwrap()
+  }
+
+  fn round_trip<T>(x: T, printed: &str)
+    where T: Serialize + de::DeserializeOwned + PartialEq + fmt::Debug {
+    let v = to_value(&x).unwrap();
+    assert_eq!(v, read(printed));
+    assert_eq!(from_value::<T>(&v).unwrap(), x);
+  }
+
+  #[test]
+  fn structs() {
+    round_trip(Point{x: 1, y: -2}, "{:x 1 :y -2}");
+    round_trip(vec![Point{x: 0, y: 0}], "[{:x 0 :y 0}]");
+  }
+
+  #[test]
+  fn enums() {
+    round_trip(Shape::Dot, ":Dot");
+    round_trip(Shape::Circle(1.5), "[:Circle 1.5]");
+    round_trip(Shape::Pair(1, 2), "[:Pair 1 2]");
+    round_trip(Shape::Rect{w: 3, h: 4}, "[:Rect {:w 3 :h 4}]");
+    // a unit variant may also be a string or a vector of just its tag
+    assert_eq!(from_value::<Shape>(&read("\"Dot\"")).unwrap(), Shape::Dot);
+    assert_eq!(from_value::<Shape>(&read("[:Dot]")).unwrap(), Shape::Dot);
+    let e = from_value::<Shape>(&read("[:Circle 1 2]")).unwrap_err();
+    assert_eq!(e.msg, "expected one value after variant :Circle, got 2 values");
+    assert!(from_value::<Shape>(&read(":Square")).is_err());
+  }
+
+  #[test]
+  fn options() {
+    round_trip(Some(Point{x: 1, y: 2}), "{:x 1 :y 2}");
+    round_trip(None::<Point>, "nil");
+    round_trip(vec![Some(1i64), None], "[1 nil]");
+  }
+
+  #[test]
+  fn maps() {
+    let mut m = HashMap::new();
+    m.insert("a".to_string(), vec![1i64, 2]);
+    round_trip(m, "{\"a\" [1 2]}");
+    let mut m = HashMap::new();
+    m.insert(1i64, true);
+    round_trip(m, "{1 true}");
+  }
+
+  #[test]
+  fn keyword_and_string_keys() {
+    // struct fields are written as keywords but read from either
+    let p = Point{x: 1, y: 2};
+    assert_eq!(from_value::<Point>(&read("{\"x\" 1 \"y\" 2}")).unwrap(), p);
+    assert_eq!(from_value::<Point>(&read("{:x 1 \"y\" 2}")).unwrap(), p);
+    // map keys are read from keywords as their names
+    let m: HashMap<String,i64> = from_value(&keyword_map(vec![("a", Int(1))])).unwrap();
+    assert_eq!(m.get("a"), Some(&1));
+    // and written as strings
+    let v = to_value(&m).unwrap();
+    assert_eq!(v, read("{\"a\" 1}"));
+    assert!(from_value::<Point>(&read("{:x 1}")).is_err());
+  }
+
+  #[test]
+  fn out_of_range() {
+    let e = from_value::<u16>(&Int(70000)).unwrap_err();
+    assert_eq!(e.kind, ErrKind::Type);
+    assert!(e.msg.contains("70000") && e.msg.contains("u16"), "{}", e.msg);
+    let e = from_value::<Shape>(&read("[:Rect {:w 1 :h 70000}]")).unwrap_err();
+    assert!(e.msg.starts_with(":h: "), "{}", e.msg);
+    assert!(from_value::<i64>(&read("18446744073709551616")).is_err());
+    assert_eq!(from_value::<u64>(&read("18446744073709551615")).unwrap(), u64::MAX);
+  }
+
+  #[test]
+  fn mal_values() {
+    // a MalVal written through to_value keeps its shape, but keywords
+    // become strings
+    assert_eq!(to_value(&read("[1 {:a (2)}]")).unwrap(), read("[1 {\"a\" [2]}]"));
+    let f = ::types::func("f", 0, None, |_| Ok(Nil));
+    let e = to_value(&vector![Int(1), f]).unwrap_err();
+    assert!(e.msg.starts_with("cannot serialize"), "{}", e.msg);
+  }
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -3,6 +3,10 @@ extern crate lazy_static;
 extern crate regex;
 extern crate itertools;
 extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+extern crate im_rc;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
@@ -12,7 +16,9 @@ use rustyline::Editor;
 #[allow(dead_code)]
 mod types;
 use types::{format_error};
+#[allow(dead_code)]
 mod reader;
+#[allow(dead_code)]
 mod printer;
 // TODO: figure out a way to avoid including env
 #[allow(dead_code)]
@@ -31,7 +37,7 @@ fn main() {
       Ok(line) => {
         rl.add_history_entry(&line);
         rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
+        if !line.is_empty() {
           match reader::read_str(line) {
             Ok(mv) => {
               println!("{}", mv.pr_str(true));
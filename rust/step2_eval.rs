@@ -7,6 +7,10 @@ extern crate lazy_static;
 extern crate regex;
 extern crate itertools;
 extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+extern crate im_rc;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
@@ -15,10 +19,11 @@ use rustyline::Editor;
 #[macro_use]
 #[allow(dead_code)]
 mod types;
-use types::{MalVal,MalArgs,MalRet,MalErr,error,format_error,func};
-use types::MalVal::{Nil,Int,Sym,List,Vector,Hash};
-use types::MalErr::{ErrString};
+use types::{MalVal,MalMap,MalSet,MalArgs,MalRet,MalErr,ErrKind,error,error_kind,format_error,func};
+use types::MalVal::{Nil,Int,Sym,List,Vector,Hash,Set};
+#[allow(dead_code)]
 mod reader;
+#[allow(dead_code)]
 mod printer;
 // TODO: figure out a way to avoid including env
 #[allow(dead_code)]
@@ -36,7 +41,7 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
   match ast {
     Sym(sym) => {
       Ok(env.get(sym)
-        .ok_or(ErrString(format!("'{}' not found", sym)))?
+        .ok_or(MalErr::new(ErrKind::Unbound, &format!("'{}' not found", sym)))?
         .clone())
     },
     List(v,_) => {
@@ -50,12 +55,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
       Ok(vector!(lst))
     },
     Hash(hm,_) => {
-      let mut new_hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+      let mut new_hm: MalMap = MalMap::default();
       for (k,v) in hm.iter() {
-        new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+        new_hm.insert(eval(k.clone(), env.clone())?, eval(v.clone(), env.clone())?);
       }
       Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
     },
+    Set(hs,_) => {
+      let mut new_hs: MalSet = MalSet::default();
+      for v in hs.iter() {
+        new_hs.insert(eval(v.clone(), env.clone())?);
+      }
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
+    },
     _ => Ok(ast.clone()),
   }
 }
@@ -63,11 +75,11 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
 fn eval(ast: MalVal, env: Env) -> MalRet {
   match ast.clone() {
     List(l,_) => {
-      if l.len() == 0 { return Ok(ast); }
+      if l.is_empty() { return Ok(ast); }
       match eval_ast(&ast, &env)? {
         List(ref el,_) => {
-          let ref f = el[0].clone();
-          f.apply(el[1..].to_vec())
+          let f = &el[0].clone();
+          f.apply(el.iter().skip(1).cloned().collect())
         },
           _ => {
             error("expected a list")
@@ -89,9 +101,12 @@ fn rep(str: &str, env: &Env) -> Result<String,MalErr> {
   Ok(print(&exp))
 }
 
-fn int_op(op: fn(i64, i64) -> i64, a:MalArgs) -> MalRet {
+fn int_op(op: fn(i64, i64) -> Option<i64>, a: &[MalVal]) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
-    (Int(a0), Int(a1)) => Ok(Int(op(a0,a1))),
+    (Int(a0), Int(a1)) => match op(a0,a1) {
+      Some(r) => Ok(Int(r)),
+      None    => error_kind(ErrKind::Arithmetic, "integer overflow"),
+    },
     _ => error("invalid int_op args"),
   }
 }
@@ -104,10 +119,15 @@ fn main() {
   }
 
   let mut repl_env = Env::default();
-  repl_env.insert("+".to_string(), func(|a:MalArgs|{int_op(|i,j|{i+j},a)}));
-  repl_env.insert("-".to_string(), func(|a:MalArgs|{int_op(|i,j|{i-j},a)}));
-  repl_env.insert("*".to_string(), func(|a:MalArgs|{int_op(|i,j|{i*j},a)}));
-  repl_env.insert("/".to_string(), func(|a:MalArgs|{int_op(|i,j|{i/j},a)}));
+  repl_env.insert("+".to_string(), func("+", 2, Some(2), |a: &[MalVal]|{int_op(i64::checked_add,a)}));
+  repl_env.insert("-".to_string(), func("-", 2, Some(2), |a: &[MalVal]|{int_op(i64::checked_sub,a)}));
+  repl_env.insert("*".to_string(), func("*", 2, Some(2), |a: &[MalVal]|{int_op(i64::checked_mul,a)}));
+  repl_env.insert("/".to_string(), func("/", 2, Some(2), |a: &[MalVal]|{
+    match a[1] {
+      Int(0) => error_kind(ErrKind::Arithmetic, "divide by zero"),
+      _      => int_op(i64::checked_div,a),
+    }
+  }));
 
   loop {
     let readline = rl.readline("user> ");
@@ -115,7 +135,7 @@ fn main() {
       Ok(line) => {
         rl.add_history_entry(&line);
         rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
+        if !line.is_empty() {
           match rep(&line, &repl_env) {
             Ok(out) => println!("{}", out),
             Err(e)  => println!("Error: {}", format_error(e)),
@@ -1,6 +1,5 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
@@ -8,6 +7,10 @@ extern crate lazy_static;
 extern crate regex;
 extern crate itertools;
 extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+extern crate im_rc;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
@@ -16,12 +19,14 @@ use rustyline::Editor;
 #[macro_use]
 #[allow(dead_code)]
 mod types;
-use types::{MalVal,MalArgs,MalRet,MalErr,error,format_error,func};
-use types::MalVal::{Nil,Int,Sym,List,Vector,Hash};
+use types::{MalVal,MalMap,MalSet,MalArgs,MalRet,MalErr,ErrKind,error,error_kind,format_error};
+use types::MalVal::{Nil,Int,Sym,List,Vector,Hash,Set};
+#[allow(dead_code)]
 mod reader;
+#[allow(dead_code)]
 mod printer;
 mod env;
-use env::{Env,env_new,env_get,env_set,env_sets};
+use env::{Env,env_new,env_get,env_set,env_set_fn};
 
 // read
 fn read(str: &str) -> MalRet {
@@ -31,7 +36,7 @@ fn read(str: &str) -> MalRet {
 // eval
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
   match ast {
-    Sym(_)  => Ok(env_get(&env, &ast)?),
+    Sym(_)  => Ok(env_get(env, ast)?),
     List(v,_) => {
       let mut lst: MalArgs = vec![];
       for a in v.iter() { lst.push(eval(a.clone(), env.clone())?) }
@@ -43,12 +48,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
       Ok(vector!(lst))
     },
     Hash(hm,_) => {
-      let mut new_hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+      let mut new_hm: MalMap = MalMap::default();
       for (k,v) in hm.iter() {
-        new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+        new_hm.insert(eval(k.clone(), env.clone())?, eval(v.clone(), env.clone())?);
       }
       Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
     },
+    Set(hs,_) => {
+      let mut new_hs: MalSet = MalSet::default();
+      for v in hs.iter() {
+        new_hs.insert(eval(v.clone(), env.clone())?);
+      }
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
+    },
     _ => Ok(ast.clone()),
   }
 }
@@ -56,7 +68,7 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
 fn eval(ast: MalVal, env: Env) -> MalRet {
   match ast.clone() {
     List(l,_) => {
-      if l.len() == 0 { return Ok(ast); }
+      if l.is_empty() { return Ok(ast); }
       let a0 = &l[0];
       match a0 {
         Sym(ref a0sym) if a0sym == "def!" => {
@@ -65,9 +77,9 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
         Sym(ref a0sym) if a0sym == "let*" => {
           let let_env = env_new(Some(env.clone()));
           let (a1, a2) = (l[1].clone(), l[2].clone());
-          match a1 {
-            List(ref binds,_) | Vector(ref binds,_) => {
-              for (b, e) in binds.iter().tuples() {
+          match a1.items() {
+            Some(binds) => {
+              for (b, e) in binds.tuples() {
                 match b {
                   Sym(_) => {
                     let _ = env_set(&let_env, b.clone(),
@@ -79,7 +91,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                 }
               }
             },
-            _ => {
+            None => {
               return error("let* with non-List bindings");
             }
           };
@@ -88,8 +100,8 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
         _ => {
           match eval_ast(&ast, &env)? {
             List(ref el,_) => {
-              let ref f = el[0].clone();
-              f.apply(el[1..].to_vec())
+              let f = &el[0].clone();
+              f.apply(el.iter().skip(1).cloned().collect())
             },
             _ => {
               error("expected a list")
@@ -113,9 +125,12 @@ fn rep(str: &str, env: &Env) -> Result<String,MalErr> {
   Ok(print(&exp))
 }
 
-fn int_op(op: fn(i64, i64) -> i64, a:MalArgs) -> MalRet {
+fn int_op(op: fn(i64, i64) -> Option<i64>, a: &[MalVal]) -> MalRet {
   match (a[0].clone(), a[1].clone()) {
-    (Int(a0), Int(a1)) => Ok(Int(op(a0,a1))),
+    (Int(a0), Int(a1)) => match op(a0,a1) {
+      Some(r) => Ok(Int(r)),
+      None    => error_kind(ErrKind::Arithmetic, "integer overflow"),
+    },
     _ => error("invalid int_op args"),
   }
 }
@@ -128,10 +143,15 @@ fn main() {
   }
 
   let repl_env = env_new(None);
-  env_sets(&repl_env, "+", func(|a:MalArgs|{int_op(|i,j|{i+j},a)}));
-  env_sets(&repl_env, "-", func(|a:MalArgs|{int_op(|i,j|{i-j},a)}));
-  env_sets(&repl_env, "*", func(|a:MalArgs|{int_op(|i,j|{i*j},a)}));
-  env_sets(&repl_env, "/", func(|a:MalArgs|{int_op(|i,j|{i/j},a)}));
+  env_set_fn(&repl_env, "+", 2, Some(2), |a: &[MalVal]|{int_op(i64::checked_add,a)});
+  env_set_fn(&repl_env, "-", 2, Some(2), |a: &[MalVal]|{int_op(i64::checked_sub,a)});
+  env_set_fn(&repl_env, "*", 2, Some(2), |a: &[MalVal]|{int_op(i64::checked_mul,a)});
+  env_set_fn(&repl_env, "/", 2, Some(2), |a: &[MalVal]|{
+    match a[1] {
+      Int(0) => error_kind(ErrKind::Arithmetic, "divide by zero"),
+      _      => int_op(i64::checked_div,a),
+    }
+  });
 
   loop {
     let readline = rl.readline("user> ");
@@ -139,7 +159,7 @@ fn main() {
       Ok(line) => {
         rl.add_history_entry(&line);
         rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
+        if !line.is_empty() {
           match rep(&line, &repl_env) {
             Ok(out) => println!("{}", out),
             Err(e)  => println!("Error: {}", format_error(e)),
@@ -1,6 +1,5 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
@@ -8,18 +7,34 @@ extern crate lazy_static;
 extern crate regex;
 extern crate itertools;
 extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+extern crate im_rc;
+extern crate serde;
+extern crate serde_json;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 #[macro_use]
+#[allow(dead_code)]
 mod types;
-use types::{MalVal,MalArgs,MalRet,MalErr,error,format_error};
-use types::MalVal::{Nil,Bool,Sym,List,Vector,Hash,Func,MalFunc};
+use types::{MalVal,MalMap,MalSet,MalArgs,MalRet,MalErr,error,format_error};
+use types::MalVal::{Nil,Bool,Sym,List,Vector,Hash,Set,Func,MalFunc};
+#[allow(dead_code)]
 mod reader;
 mod printer;
+#[allow(dead_code)]
 mod env;
+mod numeric;
+mod files;
+mod regexp;
+mod strings;
+#[allow(dead_code)]
+mod serialize;
+mod json;
 use env::{Env,env_new,env_bind,env_get,env_set,env_sets};
 #[macro_use]
 mod core;
@@ -32,7 +47,7 @@ fn read(str: &str) -> MalRet {
 // eval
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
   match ast {
-    Sym(_)  => Ok(env_get(&env, &ast)?),
+    Sym(_)  => Ok(env_get(env, ast)?),
     List(v,_) => {
       let mut lst: MalArgs = vec![];
       for a in v.iter() { lst.push(eval(a.clone(), env.clone())?) }
@@ -44,12 +59,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
       Ok(vector!(lst))
     },
     Hash(hm,_) => {
-      let mut new_hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+      let mut new_hm: MalMap = MalMap::default();
       for (k,v) in hm.iter() {
-        new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+        new_hm.insert(eval(k.clone(), env.clone())?, eval(v.clone(), env.clone())?);
       }
       Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
     },
+    Set(hs,_) => {
+      let mut new_hs: MalSet = MalSet::default();
+      for v in hs.iter() {
+        new_hs.insert(eval(v.clone(), env.clone())?);
+      }
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
+    },
     _ => Ok(ast.clone()),
   }
 }
@@ -61,7 +83,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
 
   ret = match ast.clone() {
     List(l,_) => {
-      if l.len() == 0 { return Ok(ast); }
+      if l.is_empty() { return Ok(ast); }
       let a0 = &l[0];
       match a0 {
         Sym(ref a0sym) if a0sym == "def!" => {
@@ -70,9 +92,9 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         Sym(ref a0sym) if a0sym == "let*" => {
           env = env_new(Some(env.clone()));
           let (a1, a2) = (l[1].clone(), l[2].clone());
-          match a1 {
-            List(ref binds,_) | Vector(ref binds,_) => {
-              for (b, e) in binds.iter().tuples() {
+          match a1.items() {
+            Some(binds) => {
+              for (b, e) in binds.tuples() {
                 match b {
                   Sym(_) => {
                     let _ = env_set(&env, b.clone(),
@@ -84,7 +106,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                 }
               }
             },
-            _ => {
+            None => {
               return error("let* with non-List bindings");
             }
           };
@@ -92,7 +114,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
           continue 'tco;
         },
         Sym(ref a0sym) if a0sym == "do" => {
-          match eval_ast(&list!(l[1..l.len()-1].to_vec()), &env)? {
+          match eval_ast(&list!(l.iter().skip(1).take(l.len()-2).cloned().collect::<MalArgs>()), &env)? {
             List(_,_) => {
               ast = l.last().unwrap_or(&Nil).clone();
               continue 'tco;
@@ -117,21 +139,21 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         },
         Sym(ref a0sym) if a0sym == "fn*" => {
           let (a1, a2) = (l[1].clone(), l[2].clone());
-          Ok(MalFunc{eval: eval, ast: Rc::new(a2), env: env,
+          Ok(MalFunc{eval, ast: Rc::new(a2), env,
                      params: Rc::new(a1), is_macro: false,
                      meta: Rc::new(Nil)})
         },
         _ => {
           match eval_ast(&ast, &env)? {
             List(ref el,_) => {
-              let ref f = el[0].clone();
-              let args = el[1..].to_vec();
+              let f = &el[0].clone();
+              let args: Vec<MalVal> = el.iter().skip(1).cloned().collect();
               match f {
                 Func(_,_) => f.apply(args),
                 MalFunc{ast: mast, env: menv, params, ..} => {
                   let a = &**mast;
                   let p = &**params;
-                  env = env_bind(Some(menv.clone()), p.clone(), args)?;
+                  env = env_bind(Some(menv.clone()), p.clone(), args, eval)?;
                   ast = a.clone();
                   continue 'tco;
                 },
@@ -189,7 +211,7 @@ fn main() {
       Ok(line) => {
         rl.add_history_entry(&line);
         rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
+        if !line.is_empty() {
           match rep(&line, &repl_env) {
             Ok(out) => println!("{}", out),
             Err(e)  => println!("Error: {}", format_error(e)),
@@ -1,6 +1,5 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
@@ -8,19 +7,34 @@ extern crate lazy_static;
 extern crate regex;
 extern crate itertools;
 extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+extern crate im_rc;
+extern crate serde;
+extern crate serde_json;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 #[macro_use]
+#[allow(dead_code)]
 mod types;
-use types::{MalVal,MalArgs,MalRet,MalErr,error,format_error};
-use types::MalVal::{Nil,Bool,Str,Sym,List,Vector,Hash,Func,MalFunc};
-use types::MalErr::{ErrString,ErrMalVal};
+use types::{MalVal,MalList,MalMap,MalSet,MalArgs,MalRet,MalErr,error,format_error};
+use types::MalVal::{Nil,Bool,Str,Sym,List,Vector,Hash,Set,Func,MalFunc};
+#[allow(dead_code)]
 mod reader;
 mod printer;
+#[allow(dead_code)]
 mod env;
+mod numeric;
+mod files;
+mod regexp;
+mod strings;
+#[allow(dead_code)]
+mod serialize;
+mod json;
 use env::{Env,env_new,env_bind,env_find,env_get,env_set,env_sets};
 #[macro_use]
 mod core;
@@ -32,37 +46,35 @@ fn read(str: &str) -> MalRet {
 
 // eval
 fn quasiquote(ast: &MalVal) -> MalVal {
-  match ast {
-    List(ref v,_) | Vector(ref v,_) if v.len() > 0 => {
-      let a0 = &v[0];
-      match a0 {
-        Sym(ref s) if s == "unquote" => v[1].clone(),
-        _ => {
-          match a0 {
-            List(ref v0,_) | Vector(ref v0,_) if v0.len() > 0 => {
-              match v0[0] {
-                Sym(ref s) if s == "splice-unquote" => {
-                  list![Sym("concat".to_string()),
-                        v0[1].clone(),
-                        quasiquote(&list!(v[1..].to_vec()))]
-                },
-                _ => {
-                  list![Sym("cons".to_string()),
-                        quasiquote(a0),
-                        quasiquote(&list!(v[1..].to_vec()))]
-                },
-              }
-            },
-            _ => {
-              list![Sym("cons".to_string()),
-                    quasiquote(a0),
-                    quasiquote(&list!(v[1..].to_vec()))]
-            }
-          }
-        }
+  let v: MalList = match ast {
+    List(ref l,_) if !l.is_empty() => (**l).clone(),
+    Vector(ref v,_) if !v.is_empty() => (**v).clone().into(),
+    _ => return list![Sym("quote".to_string()), ast.clone()],
+  };
+  let a0 = &v[0];
+  match a0 {
+    Sym(ref s) if s == "unquote" => v[1].clone(),
+    _ => {
+      let spliced = match a0.items() {
+        Some(mut a0_items) => match (a0_items.next(), a0_items.next()) {
+          (Some(Sym(ref s)), Some(x)) if s == "splice-unquote" => Some(x.clone()),
+          _ => None,
+        },
+        None => None,
+      };
+      match spliced {
+        Some(x) => {
+          list![Sym("concat".to_string()),
+                x,
+                quasiquote(&list!(v.rest()))]
+        },
+        None => {
+          list![Sym("cons".to_string()),
+                quasiquote(a0),
+                quasiquote(&list!(v.rest()))]
+        },
       }
-    },
-    _ => list![Sym("quote".to_string()), ast.clone()]
+    }
   }
 }
 
@@ -75,7 +87,7 @@ fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal,MalArgs)> {
             Some(e) => {
               match env_get(&e, &v[0]) {
                 Ok(f @ MalFunc{is_macro: true, ..}) => {
-                  Some((f, v[1..].to_vec()))
+                  Some((f, v.iter().skip(1).cloned().collect()))
                 },
                 _ => None,
               }
@@ -101,12 +113,12 @@ fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
     //println!("macroexpand 2: {:?}", ast);
     was_expanded = true;
   }
-  ((was_expanded, Ok(ast)))
+  (was_expanded, Ok(ast))
 }
 
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
   match ast {
-    Sym(_)  => Ok(env_get(&env, &ast)?),
+    Sym(_)  => Ok(env_get(env, ast)?),
     List(v,_) => {
       let mut lst: MalArgs = vec![];
       for a in v.iter() { lst.push(eval(a.clone(), env.clone())?) }
@@ -118,12 +130,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
       Ok(vector!(lst))
     },
     Hash(hm,_) => {
-      let mut new_hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+      let mut new_hm: MalMap = MalMap::default();
       for (k,v) in hm.iter() {
-        new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+        new_hm.insert(eval(k.clone(), env.clone())?, eval(v.clone(), env.clone())?);
       }
       Ok(Hash(Rc::new(new_hm),Rc::new(Nil)))
     },
+    Set(hs,_) => {
+      let mut new_hs: MalSet = MalSet::default();
+      for v in hs.iter() {
+        new_hs.insert(eval(v.clone(), env.clone())?);
+      }
+      Ok(Set(Rc::new(new_hs),Rc::new(Nil)))
+    },
     _ => Ok(ast.clone()),
   }
 }
@@ -144,7 +163,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         _ => (),
       }
 
-      if l.len() == 0 { return Ok(ast); }
+      if l.is_empty() { return Ok(ast); }
       let a0 = &l[0];
       match a0 {
         Sym(ref a0sym) if a0sym == "def!" => {
@@ -153,9 +172,9 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         Sym(ref a0sym) if a0sym == "let*" => {
           env = env_new(Some(env.clone()));
           let (a1, a2) = (l[1].clone(), l[2].clone());
-          match a1 {
-            List(ref binds,_) | Vector(ref binds,_) => {
-              for (b, e) in binds.iter().tuples() {
+          match a1.items() {
+            Some(binds) => {
+              for (b, e) in binds.tuples() {
                 match b {
                   Sym(_) => {
                     let _ = env_set(&env, b.clone(),
@@ -167,7 +186,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                 }
               }
             },
-            _ => {
+            None => {
               return error("let* with non-List bindings");
             }
           };
@@ -187,7 +206,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
           match r {
             MalFunc{eval, ast, env, params, ..} => {
               Ok(env_set(&env, a1.clone(),
-                 MalFunc{eval: eval, ast: ast.clone(), env: env.clone(),
+                 MalFunc{eval, ast: ast.clone(), env: env.clone(),
                          params: params.clone(), is_macro: true,
                          meta: Rc::new(Nil)})?)
             },
@@ -203,15 +222,12 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         Sym(ref a0sym) if a0sym == "try*" => {
           match eval(l[1].clone(), env.clone()) {
             Err(ref e) if l.len() >= 3 => {
-              let exc = match e {
-                ErrMalVal(mv) => mv.clone(),
-                ErrString(s)  => Str(s.to_string()),
-              };
+              let exc = e.value();
               match l[2].clone() {
                 List(c,_) => {
                   let catch_env = env_bind(Some(env.clone()),
                                            list!(vec![c[1].clone()]),
-                                           vec![exc])?;
+                                           vec![exc], eval)?;
                   eval(c[2].clone(), catch_env)
                 },
                 _ => error("invalid catch block"),
@@ -221,7 +237,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
           }
         },
         Sym(ref a0sym) if a0sym == "do" => {
-          match eval_ast(&list!(l[1..l.len()-1].to_vec()), &env)? {
+          match eval_ast(&list!(l.iter().skip(1).take(l.len()-2).cloned().collect::<MalArgs>()), &env)? {
             List(_,_) => {
               ast = l.last().unwrap_or(&Nil).clone();
               continue 'tco;
@@ -246,7 +262,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         },
         Sym(ref a0sym) if a0sym == "fn*" => {
           let (a1, a2) = (l[1].clone(), l[2].clone());
-          Ok(MalFunc{eval: eval, ast: Rc::new(a2), env: env,
+          Ok(MalFunc{eval, ast: Rc::new(a2), env,
                      params: Rc::new(a1), is_macro: false,
                      meta: Rc::new(Nil)})
         },
@@ -260,14 +276,14 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
         _ => {
           match eval_ast(&ast, &env)? {
             List(ref el,_) => {
-              let ref f = el[0].clone();
-              let args = el[1..].to_vec();
+              let f = &el[0].clone();
+              let args: Vec<MalVal> = el.iter().skip(1).cloned().collect();
               match f {
                 Func(_,_) => f.apply(args),
                 MalFunc{ast: mast, env: menv, params, ..} => {
                   let a = &**mast;
                   let p = &**params;
-                  env = env_bind(Some(menv.clone()), p.clone(), args)?;
+                  env = env_bind(Some(menv.clone()), p.clone(), args, eval)?;
                   ast = a.clone();
                   continue 'tco;
                 },
@@ -317,7 +333,7 @@ fn main() {
   for (k, v) in core::ns() {
     env_sets(&repl_env, k, v);
   }
-  env_sets(&repl_env, "*ARGV*", list!(args.map(Str).collect()));
+  env_sets(&repl_env, "*ARGV*", list!(args.map(Str).collect::<Vec<MalVal>>()));
 
   // core.mal: defined using the language itself
   let _ = rep("(def! not (fn* (a) (if a false true)))", &repl_env);
@@ -344,7 +360,7 @@ fn main() {
       Ok(line) => {
         rl.add_history_entry(&line);
         rl.save_history(".mal-history").unwrap();
-        if line.len() > 0 {
+        if !line.is_empty() {
           match rep(&line, &repl_env) {
             Ok(out) => println!("{}", out),
             Err(e)  => println!("Error: {}", format_error(e)),
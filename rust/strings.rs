@@ -0,0 +1,386 @@
+use std::rc::Rc;
+use std::iter::Peekable;
+use std::str::Chars;
+use num_traits::ToPrimitive;
+
+use types::{MalVal,MalRet,MalErr,ErrKind,error,error_kind};
+use types::MalVal::{Nil,Bool,Int,Float,Str,List,Vector,Regex};
+use core::str_arg;
+
+// strings are indexed by char, not byte, so multi-byte chars count once;
+// the byte- functions index by byte instead
+
+fn index_arg(name: &str, v: &MalVal) -> Result<usize,MalErr> {
+  match v {
+    Int(i) if *i >= 0 => Ok(*i as usize),
+    _ => Err(MalErr::new(ErrKind::Type,
+                         &format!("{}: expected a non-negative index, got {}", name, v.pr_str(true)))),
+  }
+}
+
+// the byte offset of char index i in s, if s has at least i chars
+fn byte_offset(s: &str, i: usize) -> Option<usize> {
+  s.char_indices().map(|(b, _)| b).chain(Some(s.len())).nth(i)
+}
+
+// (subs s start) or (subs s start end)
+pub fn subs(a: &[MalVal]) -> MalRet {
+  let s = str_arg("subs", &a[0])?;
+  let start = index_arg("subs", &a[1])?;
+  let end = match a.get(2) {
+    Some(e) => Some(index_arg("subs", e)?),
+    None    => None,
+  };
+  let from = byte_offset(s, start);
+  let to = match end {
+    Some(e) if e >= start => byte_offset(s, e),
+    Some(_) => None,
+    None    => Some(s.len()),
+  };
+  match (from, to) {
+    (Some(from), Some(to)) => Ok(Str(s[from..to].to_string())),
+    _ => error(&format!("subs: index out of range for a string of {} chars",
+                        s.chars().count())),
+  }
+}
+
+// (split s sep) or (split s sep limit), where sep is a string or regex;
+// without a limit, trailing empty strings are dropped
+pub fn split(a: &[MalVal]) -> MalRet {
+  let s = str_arg("split", &a[0])?;
+  let limit = match a.get(2) {
+    Some(Int(n)) if *n > 0 => Some(*n as usize),
+    Some(l) => return error_kind(ErrKind::Type, &format!("split: expected a positive limit, got {}",
+                                                         l.pr_str(true))),
+    None => None,
+  };
+  let mut parts: Vec<&str> = match (&a[1], limit) {
+    (Str(sep), _) if sep.is_empty() => {
+      let mut parts: Vec<&str> = s.char_indices().map(|(i, c)| &s[i..i + c.len_utf8()]).collect();
+      if let Some(n) = limit.filter(|n| *n < parts.len()) {
+        let rest = byte_offset(s, n - 1).unwrap();
+        parts.truncate(n - 1);
+        parts.push(&s[rest..]);
+      }
+      parts
+    },
+    (Str(sep), Some(n)) => s.splitn(n, &sep[..]).collect(),
+    (Str(sep), None)    => s.split(&sep[..]).collect(),
+    (Regex(r), Some(n)) => r.re.splitn(s, n).collect(),
+    (Regex(r), None)    => r.re.split(s).collect(),
+    (sep, _) => return error_kind(ErrKind::Type, &format!("split: expected a string or regex, got {}",
+                                                          sep.pr_str(true))),
+  };
+  // a regex that matches empty at the start doesn't give an empty first
+  // part, as in Clojure
+  if let Regex(ref r) = a[1] {
+    if parts.len() > 1 && parts[0].is_empty() && r.re.find(s).is_some_and(|m| m.end() == 0) {
+      parts.remove(0);
+    }
+  }
+  if limit.is_none() {
+    while parts.last() == Some(&"") {
+      parts.pop();
+    }
+  }
+  Ok(vector!(parts.into_iter().map(|p| Str(p.to_string())).collect::<Vec<MalVal>>()))
+}
+
+// splits on \n or \r\n
+pub fn split_lines(a: &[MalVal]) -> MalRet {
+  let s = str_arg("split-lines", &a[0])?;
+  Ok(vector!(s.lines().map(|l| Str(l.to_string())).collect::<Vec<MalVal>>()))
+}
+
+// (join coll) or (join sep coll); each item is joined as by str
+pub fn join(a: &[MalVal]) -> MalRet {
+  let (sep, coll) = match a.len() {
+    1 => ("", &a[0]),
+    _ => (str_arg("join", &a[0])?, &a[1]),
+  };
+  let strs: Vec<String> = coll.seq_vec()?.iter()
+    .map(|x| x.try_pr_str(false))
+    .collect::<Result<_,_>>()?;
+  Ok(Str(strs.join(sep)))
+}
+
+pub fn trim(a: &[MalVal]) -> MalRet { Ok(Str(str_arg("trim", &a[0])?.trim().to_string())) }
+pub fn triml(a: &[MalVal]) -> MalRet { Ok(Str(str_arg("triml", &a[0])?.trim_start().to_string())) }
+pub fn trimr(a: &[MalVal]) -> MalRet { Ok(Str(str_arg("trimr", &a[0])?.trim_end().to_string())) }
+
+pub fn upper_case(a: &[MalVal]) -> MalRet { Ok(Str(str_arg("upper-case", &a[0])?.to_uppercase())) }
+pub fn lower_case(a: &[MalVal]) -> MalRet { Ok(Str(str_arg("lower-case", &a[0])?.to_lowercase())) }
+
+pub fn starts_with_q(a: &[MalVal]) -> MalRet {
+  Ok(Bool(str_arg("starts-with?", &a[0])?.starts_with(str_arg("starts-with?", &a[1])?)))
+}
+
+pub fn ends_with_q(a: &[MalVal]) -> MalRet {
+  Ok(Bool(str_arg("ends-with?", &a[0])?.ends_with(str_arg("ends-with?", &a[1])?)))
+}
+
+pub fn includes_q(a: &[MalVal]) -> MalRet {
+  Ok(Bool(str_arg("includes?", &a[0])?.contains(str_arg("includes?", &a[1])?)))
+}
+
+// (index-of s x) or (index-of s x from): the char index of the first x
+// in s at or after from, or nil
+pub fn index_of(a: &[MalVal]) -> MalRet {
+  let s = str_arg("index-of", &a[0])?;
+  let x = str_arg("index-of", &a[1])?;
+  let from = match a.get(2) {
+    Some(f) => index_arg("index-of", f)?,
+    None    => 0,
+  };
+  let start = match byte_offset(s, from) {
+    Some(b) => b,
+    None    => return Ok(Nil),
+  };
+  Ok(match s[start..].find(x) {
+    Some(b) => Int((from + s[start..start + b].chars().count()) as i64),
+    None    => Nil,
+  })
+}
+
+// true for nil and for strings of only whitespace
+pub fn blank_q(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Nil => Ok(Bool(true)),
+    _   => Ok(Bool(str_arg("blank?", &a[0])?.trim().is_empty())),
+  }
+}
+
+pub fn byte_count(a: &[MalVal]) -> MalRet {
+  Ok(Int(str_arg("byte-count", &a[0])?.len() as i64))
+}
+
+fn byte_range_error(name: &str, s: &str) -> MalRet {
+  error(&format!("{}: index out of range for a string of {} bytes", name, s.len()))
+}
+
+// (byte-subs s start) or (byte-subs s start end) by byte offsets, which
+// must fall between chars
+pub fn byte_subs(a: &[MalVal]) -> MalRet {
+  let s = str_arg("byte-subs", &a[0])?;
+  let start = index_arg("byte-subs", &a[1])?;
+  let end = match a.get(2) {
+    Some(e) => index_arg("byte-subs", e)?,
+    None    => s.len(),
+  };
+  if start > end || end > s.len() {
+    return byte_range_error("byte-subs", s);
+  }
+  match s.get(start..end) {
+    Some(sub) => Ok(Str(sub.to_string())),
+    None => error(&format!("byte-subs: byte offset {} is inside a char",
+                           if s.is_char_boundary(start) { end } else { start })),
+  }
+}
+
+// the byte at offset i of s, as an integer
+pub fn byte_at(a: &[MalVal]) -> MalRet {
+  let s = str_arg("byte-at", &a[0])?;
+  match s.as_bytes().get(index_arg("byte-at", &a[1])?) {
+    Some(b) => Ok(Int(*b as i64)),
+    None    => byte_range_error("byte-at", s),
+  }
+}
+
+// (byte-index-of s x) or (byte-index-of s x from): the byte offset of
+// the first x in s at or after byte offset from, or nil
+pub fn byte_index_of(a: &[MalVal]) -> MalRet {
+  let s = str_arg("byte-index-of", &a[0])?;
+  let x = str_arg("byte-index-of", &a[1])?;
+  let from = match a.get(2) {
+    Some(f) => index_arg("byte-index-of", f)?,
+    None    => 0,
+  };
+  // a search from inside a char starts at the next one
+  let start = (from..=s.len()).find(|b| s.is_char_boundary(*b));
+  Ok(match start.and_then(|st| s[st..].find(x).map(|b| st + b)) {
+    Some(b) => Int(b as i64),
+    None    => Nil,
+  })
+}
+
+// a string with its chars reversed, or a seq's items as a list
+pub fn reverse(a: &[MalVal]) -> MalRet {
+  match a[0] {
+    Str(ref s) => Ok(Str(s.chars().rev().collect())),
+    _ => {
+      let mut items = a[0].seq_vec()?;
+      items.reverse();
+      Ok(list!(items))
+    },
+  }
+}
+
+// a %-spec of format: flags, a width and a precision
+struct Spec {
+  left: bool,
+  zero: bool,
+  plus: bool,
+  space: bool,
+  width: usize,
+  prec: Option<usize>,
+}
+
+impl Spec {
+  fn sign(&self, neg: bool) -> &'static str {
+    match (neg, self.plus, self.space) {
+      (true, _, _)     => "-",
+      (false, true, _) => "+",
+      (false, _, true) => " ",
+      _                => "",
+    }
+  }
+
+  // pads s to the width, with zeros after any sign when zero is set
+  fn pad(&self, sign: &str, s: String) -> String {
+    let len = sign.chars().count() + s.chars().count();
+    let fill = self.width.saturating_sub(len);
+    if self.left {
+      format!("{}{}{}", sign, s, " ".repeat(fill))
+    } else if self.zero {
+      format!("{}{}{}", sign, "0".repeat(fill), s)
+    } else {
+      format!("{}{}{}", " ".repeat(fill), sign, s)
+    }
+  }
+}
+
+fn format_int(spec: &Spec, conv: char, v: &MalVal) -> Result<String,MalErr> {
+  let (neg, digits) = match (v, conv) {
+    (Int(i), 'd') => (*i < 0, i.unsigned_abs().to_string()),
+    (Int(i), 'x') => (false, format!("{:x}", i)),
+    (Int(i), 'X') => (false, format!("{:X}", i)),
+    (Int(i), 'o') => (false, format!("{:o}", i)),
+    (MalVal::BigInt(b), _) => {
+      let radix = match conv { 'x' | 'X' => 16, 'o' => 8, _ => 10 };
+      let s = b.to_str_radix(radix);
+      let s = if conv == 'X' { s.to_uppercase() } else { s };
+      match s.strip_prefix('-') {
+        Some(d) => (true, d.to_string()),
+        None    => (false, s),
+      }
+    },
+    _ => return Err(MalErr::new(ErrKind::Type,
+                                &format!("format: %{} expects an integer, got {}",
+                                         conv, v.pr_str(true)))),
+  };
+  Ok(spec.pad(spec.sign(neg), digits))
+}
+
+fn format_float(spec: &Spec, conv: char, v: &MalVal) -> Result<String,MalErr> {
+  let f = match v {
+    Int(i) => *i as f64,
+    Float(f) => *f,
+    MalVal::BigInt(b) => b.to_f64().unwrap_or(f64::NAN),
+    MalVal::Ratio(r) => r.to_f64().unwrap_or(f64::NAN),
+    _ => return Err(MalErr::new(ErrKind::Type,
+                                &format!("format: %{} expects a number, got {}",
+                                         conv, v.pr_str(true)))),
+  };
+  let prec = spec.prec.unwrap_or(6);
+  let body = if !f.is_finite() {
+    if f.is_nan() { "NaN".to_string() } else { "Infinity".to_string() }
+  } else if conv == 'f' {
+    format!("{:.*}", prec, f.abs())
+  } else {
+    // rust writes 1.5e3 where printf writes 1.5e+03
+    let s = format!("{:.*e}", prec, f.abs());
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+  };
+  Ok(spec.pad(spec.sign(f.is_sign_negative() && !f.is_nan()), body))
+}
+
+// the largest width or precision format accepts, so that a stray digit
+// can't ask for gigabytes of padding
+const MAX_FORMAT_COUNT: usize = 10_000;
+
+// reads the digits of a width or precision
+fn format_count(chars: &mut Peekable<Chars>, what: &str) -> Result<usize,MalErr> {
+  let mut n: usize = 0;
+  while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+    n = n.checked_mul(10).and_then(|n| n.checked_add(d as usize))
+      .filter(|n| *n <= MAX_FORMAT_COUNT)
+      .ok_or_else(|| MalErr::new(ErrKind::Type,
+                                 &format!("format: {} is larger than {}", what, MAX_FORMAT_COUNT)))?;
+    chars.next();
+  }
+  Ok(n)
+}
+
+// printf-style formatting with %s %d %x %X %o %f %e %c, %n for a newline
+// and %% for a percent sign; flags - 0 + and space, a width and a
+// precision work as in printf
+pub fn format(a: &[MalVal]) -> MalRet {
+  let fmt = str_arg("format", &a[0])?;
+  let mut args = a[1..].iter();
+  let mut res = String::new();
+  let mut chars = fmt.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '%' {
+      res.push(c);
+      continue;
+    }
+    let mut spec = Spec{left: false, zero: false, plus: false, space: false,
+                        width: 0, prec: None};
+    while let Some(&f) = chars.peek() {
+      match f {
+        '-' => spec.left = true,
+        '0' => spec.zero = true,
+        '+' => spec.plus = true,
+        ' ' => spec.space = true,
+        _   => break,
+      }
+      chars.next();
+    }
+    spec.width = format_count(&mut chars, "width")?;
+    if chars.peek() == Some(&'.') {
+      chars.next();
+      spec.prec = Some(format_count(&mut chars, "precision")?);
+    }
+    let conv = match chars.next() {
+      Some(conv) => conv,
+      None => return error("format: incomplete format specifier at end of string"),
+    };
+    let mut arg = || args.next().ok_or_else(|| {
+      MalErr::new(ErrKind::Error, &format!("format: missing argument for %{}", conv))
+    });
+    let s = match conv {
+      '%' => "%".to_string(),
+      'n' => "\n".to_string(),
+      's' => {
+        let s = arg()?.try_pr_str(false)?;
+        let s = match spec.prec {
+          Some(p) => s.chars().take(p).collect(),
+          None    => s,
+        };
+        spec.zero = false;
+        spec.pad("", s)
+      },
+      'c' => {
+        let c = match arg()? {
+          Str(s) if s.chars().count() == 1 => s.clone(),
+          Int(i) if i.to_u32().and_then(char::from_u32).is_some() => {
+            i.to_u32().and_then(char::from_u32).unwrap().to_string()
+          },
+          v => return error_kind(ErrKind::Type, &format!("format: %c expects a char, got {}",
+                                                         v.pr_str(true))),
+        };
+        spec.zero = false;
+        spec.pad("", c)
+      },
+      'd' | 'x' | 'X' | 'o' => format_int(&spec, conv, arg()?)?,
+      'f' | 'e' => format_float(&spec, conv, arg()?)?,
+      _ => return error(&format!("format: unknown conversion %{}", conv)),
+    };
+    res.push_str(&s);
+  }
+  Ok(Str(res))
+}
+
+// vim: ts=2:sw=2:expandtab
@@ -1,25 +1,50 @@
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{RefCell,OnceCell};
+use std::ops::{Deref,DerefMut};
+use std::hash::{Hash as StdHash,Hasher};
+use std::{fmt,mem};
+use std::iter::FromIterator;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
-use itertools::Itertools;
+use fnv::{FnvHashSet,FnvHasher,FnvBuildHasher};
+use im_rc;
+use regex;
+use itertools::{Itertools,Either};
+use num_bigint;
+use num_traits::ToPrimitive;
+use num_rational::BigRational;
 
-use types::MalErr::{ErrString,ErrMalVal};
-use types::MalVal::{Nil,Bool,Int,Str,Sym,List,Vector,Hash,Func,MalFunc,Atom};
+use types::MalVal::{Nil,Bool,Int,Float,Str,Keyword,Sym,List,Vector,Hash,Set,LazySeq,Tagged,Regex,Func,MalFunc,Atom,Reduced};
 use env::{Env,env_bind};
+use reader::{Span,span_of};
+
+// persistent collections that share structure between versions: a cons
+// list backs lists, so cons and rest are O(1), an RRB vector backs
+// vectors, so conj and nth are cheap, and HAMTs back hash-maps and sets
+pub type MalVec = im_rc::Vector<MalVal>;
+pub type MalMap = im_rc::HashMap<MalVal, MalVal, FnvBuildHasher>;
+pub type MalSet = im_rc::HashSet<MalVal, FnvBuildHasher>;
 
 #[derive(Debug, Clone)]
 pub enum MalVal {
     Nil,
     Bool(bool),
     Int(i64),
-    //Float(f64),
+    Float(f64),
+    BigInt(Rc<num_bigint::BigInt>),
+    Ratio(Rc<BigRational>),
     Str(String),
+    Keyword(Rc<str>),
     Sym(String),
-    List(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Hash(Rc<FnvHashMap<String, MalVal>>, Rc<MalVal>),
-    Func(fn(MalArgs) -> MalRet, Rc<MalVal>),
+    List(Rc<MalList>, Rc<MalVal>),
+    Vector(Rc<MalVec>, Rc<MalVal>),
+    Hash(Rc<MalMap>, Rc<MalVal>),
+    Set(Rc<MalSet>, Rc<MalVal>),
+    LazySeq(Rc<Lazy>, Rc<MalVal>),
+    // an edn tagged element, such as #inst "2024-01-01", with no reader
+    // function to turn it into anything else
+    Tagged(Rc<str>, Rc<MalVal>),
+    Regex(Rc<MalRegex>),
+    Func(Rc<NativeFn>, Rc<MalVal>),
     MalFunc {
       eval: fn(ast: MalVal, env: Env) -> MalRet,
       ast: Rc<MalVal>,
@@ -29,105 +54,683 @@ pub enum MalVal {
       meta: Rc<MalVal>,
     },
     Atom(Rc<RefCell<MalVal>>),
+    // a value wrapped by reduced, which stops a reduce early
+    Reduced(Rc<MalVal>),
+}
+
+// a singly linked list whose tails are shared, so that cons and rest
+// never copy; its length is kept so that count is O(1) too
+#[derive(Clone, Default)]
+pub struct MalList {
+  head: Option<Rc<Node>>,
+  len: usize,
+}
+
+struct Node {
+  first: MalVal,
+  rest: Option<Rc<Node>>,
+}
+
+impl MalList {
+  pub fn new() -> MalList {
+    MalList{head: None, len: 0}
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn cons(&self, x: MalVal) -> MalList {
+    MalList{head: Some(Rc::new(Node{first: x, rest: self.head.clone()})),
+            len: self.len + 1}
+  }
+
+  // every item after the first, or the empty list
+  pub fn rest(&self) -> MalList {
+    self.skip(1)
+  }
+
+  // the items after the first n, sharing them with this list
+  pub fn skip(&self, n: usize) -> MalList {
+    let mut head = &self.head;
+    for _ in 0..n {
+      match head {
+        Some(node) => head = &node.rest,
+        None => break,
+      }
+    }
+    MalList{head: head.clone(), len: self.len.saturating_sub(n)}
+  }
+
+  // items before the front of tail, which is shared
+  pub fn prepend(tail: MalList, items: Vec<MalVal>) -> MalList {
+    items.into_iter().rev().fold(tail, |l, x| l.cons(x))
+  }
+
+  pub fn front(&self) -> Option<&MalVal> {
+    self.head.as_ref().map(|node| &node.first)
+  }
+
+  pub fn get(&self, i: usize) -> Option<&MalVal> {
+    self.iter().nth(i)
+  }
+
+  pub fn last(&self) -> Option<&MalVal> {
+    self.iter().last()
+  }
+
+  pub fn iter(&self) -> Iter<'_> {
+    Iter{next: self.head.as_deref(), len: self.len}
+  }
+}
+
+#[derive(Clone)]
+pub struct Iter<'a> {
+  next: Option<&'a Node>,
+  len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+  type Item = &'a MalVal;
+  fn next(&mut self) -> Option<&'a MalVal> {
+    self.next.map(|node| {
+      self.next = node.rest.as_deref();
+      self.len -= 1;
+      &node.first
+    })
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (self.len, Some(self.len))
+  }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a MalList {
+  type Item = &'a MalVal;
+  type IntoIter = Iter<'a>;
+  fn into_iter(self) -> Iter<'a> {
+    self.iter()
+  }
+}
+
+impl std::ops::Index<usize> for MalList {
+  type Output = MalVal;
+  fn index(&self, i: usize) -> &MalVal {
+    match self.get(i) {
+      Some(x) => x,
+      None => panic!("index {} out of range for a list of {}", i, self.len),
+    }
+  }
+}
+
+impl FromIterator<MalVal> for MalList {
+  fn from_iter<I: IntoIterator<Item = MalVal>>(iter: I) -> MalList {
+    MalList::prepend(MalList::new(), iter.into_iter().collect())
+  }
+}
+
+impl From<Vec<MalVal>> for MalList {
+  fn from(v: Vec<MalVal>) -> MalList {
+    MalList::prepend(MalList::new(), v)
+  }
+}
+
+impl From<MalVec> for MalList {
+  fn from(v: MalVec) -> MalList {
+    v.into_iter().collect()
+  }
+}
+
+impl PartialEq for MalList {
+  fn eq(&self, other: &MalList) -> bool {
+    self.len == other.len && self.iter().eq(other.iter())
+  }
+}
+
+impl fmt::Debug for MalList {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_list().entries(self.iter()).finish()
+  }
+}
+
+// unlinks the nodes no other list shares one at a time, where the
+// default drop would recurse once per node
+impl Drop for MalList {
+  fn drop(&mut self) {
+    let mut next = self.head.take();
+    while let Some(node) = next {
+      next = match Rc::try_unwrap(node) {
+        Ok(mut node) => node.rest.take(),
+        Err(_) => None,
+      };
+    }
+  }
+}
+
+// a sequence whose items are computed on demand: the thunk yields any
+// seqable value, which is realized into its first item and the rest
+pub struct Lazy {
+  state: RefCell<LazyState>,
+}
+
+enum LazyState {
+  Thunk(Rc<dyn Fn() -> MalRet>),
+  Realizing,
+  Cell(Option<(MalVal, MalVal)>),
+}
+
+impl fmt::Debug for Lazy {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Lazy")
+  }
+}
+
+impl Lazy {
+  // a thunk that fails is kept, so realizing the seq again retries it
+  fn realize(&self) -> Result<Option<(MalVal,MalVal)>,MalErr> {
+    let thunk = match *self.state.borrow() {
+      LazyState::Cell(ref cell) => return Ok(cell.clone()),
+      LazyState::Realizing => {
+        return Err(MalErr::new(ErrKind::Error, "lazy seq depends on its own value"))
+      },
+      LazyState::Thunk(ref f) => f.clone(),
+    };
+    *self.state.borrow_mut() = LazyState::Realizing;
+    match thunk().and_then(|v| v.first_rest()) {
+      Ok(cell) => {
+        *self.state.borrow_mut() = LazyState::Cell(cell.clone());
+        Ok(cell)
+      },
+      Err(e) => {
+        *self.state.borrow_mut() = LazyState::Thunk(thunk);
+        Err(e)
+      },
+    }
+  }
+}
+
+// unlinks a long realized chain one cell at a time, where the default
+// drop would recurse once per cell
+impl Drop for Lazy {
+  fn drop(&mut self) {
+    let mut next = match *self.state.get_mut() {
+      LazyState::Cell(Some((_, ref mut rest))) => mem::replace(rest, Nil),
+      _ => return,
+    };
+    while let LazySeq(ls, _) = next {
+      next = match Rc::try_unwrap(ls) {
+        Ok(mut ls) => match *ls.state.get_mut() {
+          LazyState::Cell(Some((_, ref mut rest))) => mem::replace(rest, Nil),
+          _ => Nil,
+        },
+        Err(_) => Nil,
+      };
+    }
+  }
 }
 
+// a compiled regex; whole is the same pattern anchored at both ends,
+// compiled the first time re-matches needs it
+pub struct MalRegex {
+  pub re: regex::Regex,
+  whole: OnceCell<regex::Regex>,
+}
+
+impl fmt::Debug for MalRegex {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "MalRegex({:?})", self.re.as_str())
+  }
+}
+
+impl MalRegex {
+  pub fn whole(&self) -> &regex::Regex {
+    self.whole.get_or_init(|| {
+      regex::Regex::new(&format!("^(?:{})$", self.re.as_str())).unwrap()
+    })
+  }
+}
+
+// a function implemented in rust, and how many args it accepts; f may
+// be a closure over state of its own
+pub struct NativeFn {
+  pub name: String,
+  pub min: usize,
+  pub max: Option<usize>,
+  pub f: Box<MalFn>,
+}
+
+impl fmt::Debug for NativeFn {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("NativeFn").field("name", &self.name)
+      .field("min", &self.min).field("max", &self.max).finish()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrKind {
+  Arity,
+  Type,
+  Unbound,
+  Io,
+  Throw,
+  Reader,
+  Arithmetic,
+  Error,
+}
+
+impl ErrKind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      ErrKind::Arity   => "arity",
+      ErrKind::Type    => "type",
+      ErrKind::Unbound => "unbound-symbol",
+      ErrKind::Io      => "io",
+      ErrKind::Throw   => "throw",
+      ErrKind::Reader  => "reader",
+      ErrKind::Arithmetic => "arithmetic",
+      ErrKind::Error   => "error",
+    }
+  }
+}
+
+// a MalFunc call that was active when an error was raised
+#[derive(Debug, Clone)]
+pub struct Frame {
+  pub name: String,
+  pub span: Option<Span>,
+}
+
+#[derive(Debug)]
+pub struct ErrInfo {
+  pub kind: ErrKind,
+  pub msg: String,
+  pub data: MalVal,
+  pub span: Option<Span>,
+  pub trace: Vec<Frame>,
+}
+
+// boxed so that MalRet stays small on the success path
 #[derive(Debug)]
-pub enum MalErr {
-  ErrString(String),
-  ErrMalVal(MalVal),
+pub struct MalErr(Box<ErrInfo>);
+
+impl Deref for MalErr {
+  type Target = ErrInfo;
+  fn deref(&self) -> &ErrInfo { &self.0 }
+}
+
+impl DerefMut for MalErr {
+  fn deref_mut(&mut self) -> &mut ErrInfo { &mut self.0 }
 }
 
 pub type MalArgs = Vec<MalVal>;
 pub type MalRet = Result<MalVal,MalErr>;
+pub type MalFn = dyn Fn(&[MalVal]) -> MalRet;
 
 // type utility macros
 
 macro_rules! list {
   ($seq:expr) => {{
-    List(Rc::new($seq),Rc::new(Nil))
+    List(Rc::new($seq.into()),Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    List(Rc::new(v),Rc::new(Nil))
+    List(Rc::new(v.into()),Rc::new(Nil))
   }}
 }
 
 macro_rules! vector {
   ($seq:expr) => {{
-    Vector(Rc::new($seq),Rc::new(Nil))
+    Vector(Rc::new($seq.into()),Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    Vector(Rc::new(v),Rc::new(Nil))
+    Vector(Rc::new(v.into()),Rc::new(Nil))
   }}
 }
 
 // type utility functions
 
 pub fn error(s: &str) -> MalRet {
-  Err(ErrString(s.to_string()))
+  Err(MalErr::new(ErrKind::Error, s))
+}
+
+pub fn error_kind(kind: ErrKind, s: &str) -> MalRet {
+  Err(MalErr::new(kind, s))
+}
+
+pub fn arity_error<T>(n: usize, name: &str) -> Result<T,MalErr> {
+  Err(MalErr::new(ErrKind::Arity,
+                  &format!("wrong number of args ({}) passed to {}", n, name)))
+}
+
+// the message and where it was raised, without the trace
+impl fmt::Display for MalErr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.kind {
+      ErrKind::Throw => write!(f, "{}", self.data.pr_str(true))?,
+      _              => write!(f, "{}", self.msg)?,
+    }
+    match self.span {
+      Some(ref span) => write!(f, " ({})", span),
+      None           => Ok(()),
+    }
+  }
 }
 
+impl std::error::Error for MalErr {}
+
 pub fn format_error(e: MalErr) -> String {
-  match e {
-    ErrString(s)  => s.clone(),
-    ErrMalVal(mv) => mv.pr_str(true),
+  let mut s = e.to_string();
+  for f in e.trace.iter() {
+    match f.span {
+      Some(ref span) => s += &format!("\n  in {} ({})", f.name, span),
+      None           => s += &format!("\n  in {}", f.name),
+    }
+  }
+  s
+}
+
+impl MalErr {
+  pub fn new(kind: ErrKind, msg: &str) -> MalErr {
+    MalErr(Box::new(ErrInfo{kind, msg: msg.to_string(), data: Nil,
+                            span: None, trace: vec![]}))
+  }
+
+  pub fn throw(data: MalVal) -> MalErr {
+    let msg = match data {
+      Str(ref s) => s.clone(),
+      _          => data.pr_str(true),
+    };
+    MalErr(Box::new(ErrInfo{kind: ErrKind::Throw, msg, data,
+                            span: None, trace: vec![]}))
+  }
+
+  pub fn at(mut self, span: Option<Span>) -> MalErr {
+    if self.span.is_none() {
+      self.span = span;
+    }
+    self
+  }
+
+  // the value bound by a plain catch*: what was thrown, or the message
+  pub fn value(&self) -> MalVal {
+    match self.kind {
+      ErrKind::Throw => self.data.clone(),
+      _              => Str(self.msg.clone()),
+    }
+  }
+
+  // the hash-map bound to *error* in stepA's catch*, scoped to that
+  // catch* body alone
+  pub fn to_mal(&self) -> MalVal {
+    let trace: Vec<MalVal> = self.trace.iter().map(|f| {
+      let mut kvs = vec![("fn", Str(f.name.clone()))];
+      kvs.extend(span_kvs(&f.span));
+      keyword_map(kvs)
+    }).collect();
+    let mut kvs = vec![("kind", keyword(self.kind.name())),
+                       ("message", Str(self.msg.clone())),
+                       ("data", self.data.clone()),
+                       ("trace", vector!(trace))];
+    kvs.extend(span_kvs(&self.span));
+    keyword_map(kvs)
   }
 }
 
+fn span_kvs(span: &Option<Span>) -> Vec<(&'static str, MalVal)> {
+  match span {
+    Some(span) => {
+      let mut kvs = vec![("line", Int(span.line as i64)),
+                         ("column", Int(span.col as i64))];
+      if let Some(ref file) = span.file {
+        kvs.push(("file", Str(file.to_string())));
+      }
+      kvs
+    },
+    None => vec![],
+  }
+}
+
+// an Int when the value fits in an i64; only N literals and arithmetic
+// on them give a BigInt that an Int could hold
+pub fn bigint(b: num_bigint::BigInt) -> MalVal {
+  match b.to_i64() {
+    Some(i) => Int(i),
+    None    => MalVal::BigInt(Rc::new(b)),
+  }
+}
+
+// an integer when the denominator is 1, so that a Ratio is never whole
+pub fn ratio(r: BigRational) -> MalVal {
+  if r.is_integer() {
+    bigint(r.to_integer())
+  } else {
+    MalVal::Ratio(Rc::new(r))
+  }
+}
+
+thread_local! {
+  static KEYWORDS: RefCell<FnvHashSet<Rc<str>>> = RefCell::new(FnvHashSet::default());
+}
+
+// keywords are interned, so each name is only stored once
+pub fn keyword(name: &str) -> MalVal {
+  KEYWORDS.with(|kws| {
+    let mut kws = kws.borrow_mut();
+    if let Some(k) = kws.get(name) {
+      return Keyword(k.clone());
+    }
+    let k: Rc<str> = Rc::from(name);
+    kws.insert(k.clone());
+    Keyword(k)
+  })
+}
+
+// a hash-map with keyword keys
+pub fn keyword_map(kvs: Vec<(&str, MalVal)>) -> MalVal {
+  let hm: MalMap = kvs.into_iter()
+    .map(|(k, v)| (keyword(k), v))
+    .collect();
+  Hash(Rc::new(hm),Rc::new(Nil))
+}
+
+pub fn regex(pattern: &str) -> MalRet {
+  match regex::Regex::new(pattern) {
+    Ok(re) => Ok(Regex(Rc::new(MalRegex{re, whole: OnceCell::new()}))),
+    Err(e) => error_kind(ErrKind::Error, &format!("invalid regex: {}", e)),
+  }
+}
+
+pub fn lazy_seq<F: Fn() -> MalRet + 'static>(f: F) -> MalVal {
+  let state = LazyState::Thunk(Rc::new(f));
+  LazySeq(Rc::new(Lazy{state: RefCell::new(state)}),Rc::new(Nil))
+}
+
+// a lazy seq already realized as first followed by the seqable rest
+pub fn lazy_cons(first: MalVal, rest: MalVal) -> MalVal {
+  let state = LazyState::Cell(Some((first, rest)));
+  LazySeq(Rc::new(Lazy{state: RefCell::new(state)}),Rc::new(Nil))
+}
+
 pub fn atom(mv: &MalVal) -> MalVal {
   Atom(Rc::new(RefCell::new(mv.clone())))
 }
 
+// the items of a list or of a vector
+pub type Items<'a> = Either<Iter<'a>, im_rc::vector::Iter<'a, MalVal>>;
+
 impl MalVal {
+  // the items of a list or vector, for code that reads either alike
+  pub fn items(&self) -> Option<Items<'_>> {
+    match self {
+      List(l,_)   => Some(Either::Left(l.iter())),
+      Vector(v,_) => Some(Either::Right(v.iter())),
+      _ => None,
+    }
+  }
+
   pub fn keyword(&self) -> MalRet {
     match self {
-      Str(s) if s.starts_with("\u{29e}") => Ok(Str(s.to_string())),
-      Str(s)                             => Ok(Str(format!("\u{29e}{}", s))),
-      _ => error("invalid type for keyword"),
+      Keyword(_) => Ok(self.clone()),
+      Str(s) | Sym(s) => Ok(keyword(s)),
+      _ => error_kind(ErrKind::Type, "invalid type for keyword"),
     }
   }
 
+  // the part of a keyword or symbol after its namespace
+  pub fn name(&self) -> MalRet {
+    match self {
+      Str(s) => Ok(Str(s.to_string())),
+      Keyword(s) => Ok(Str(split_ns(s).1.to_string())),
+      Sym(s) => Ok(Str(split_ns(s).1.to_string())),
+      _ => error_kind(ErrKind::Type, "invalid type for name"),
+    }
+  }
+
+  pub fn namespace(&self) -> MalRet {
+    let ns = match self {
+      Keyword(s) => split_ns(s).0,
+      Sym(s) => split_ns(s).0,
+      _ => return error_kind(ErrKind::Type, "invalid type for namespace"),
+    };
+    Ok(ns.map_or(Nil, |ns| Str(ns.to_string())))
+  }
+
   pub fn empty_q(&self) -> MalRet {
     match self {
-      List(l,_) | Vector(l,_) => Ok(Bool(l.len() == 0)),
+      List(l,_)               => Ok(Bool(l.is_empty())),
+      Vector(v,_)             => Ok(Bool(v.is_empty())),
+      Hash(hm,_)              => Ok(Bool(hm.is_empty())),
+      Set(hs,_)               => Ok(Bool(hs.is_empty())),
+      LazySeq(..)             => Ok(Bool(self.first_rest()?.is_none())),
+      Str(s)                  => Ok(Bool(s.is_empty())),
       Nil                     => Ok(Bool(true)),
-      _ => error("invalid type for empty?"),
+      _ => error_kind(ErrKind::Type, "invalid type for empty?"),
     }
   }
 
   pub fn count(&self) -> MalRet {
     match self {
-      List(l,_) | Vector(l,_) => Ok(Int(l.len() as i64)),
+      List(l,_)               => Ok(Int(l.len() as i64)),
+      Vector(v,_)             => Ok(Int(v.len() as i64)),
+      Hash(hm,_)              => Ok(Int(hm.len() as i64)),
+      Set(hs,_)               => Ok(Int(hs.len() as i64)),
+      LazySeq(..)             => {
+        let (mut n, mut s) = (0, self.clone());
+        while let Some((_, rest)) = s.first_rest()? {
+          n += 1;
+          s = rest;
+        }
+        Ok(Int(n))
+      },
+      // chars, not bytes
+      Str(s)                  => Ok(Int(s.chars().count() as i64)),
       Nil                     => Ok(Int(0)),
-      _ => error("invalid type for count"),
+      _ => error_kind(ErrKind::Type, "invalid type for count"),
+    }
+  }
+
+  // the first item and the rest of a seqable value, or None when empty
+  pub fn first_rest(&self) -> Result<Option<(MalVal,MalVal)>,MalErr> {
+    match self {
+      Nil => Ok(None),
+      List(l,_) => Ok(l.front().map(|x| (x.clone(), List(Rc::new(l.rest()),Rc::new(Nil))))),
+      // the rest of a vector stays a vector until it is walked, so that
+      // each step is as cheap as for a list
+      Vector(v,_) => Ok(v.front().map(|x| {
+        let v = v.clone();
+        (x.clone(), lazy_seq(move || Ok(vector!(v.skip(1)))))
+      })),
+      LazySeq(ls,_) => ls.realize(),
+      // the rest of a string, map or set is made into a list only when
+      // it is needed, and then just once, so a walk over one is linear
+      Str(s) => Ok(s.chars().next().map(|c| {
+        let rest = s[c.len_utf8()..].to_string();
+        (Str(c.to_string()), lazy_seq(move || {
+          Ok(list!(rest.chars().map(|c| Str(c.to_string())).collect::<MalList>()))
+        }))
+      })),
+      Hash(hm,_) => Ok(hm.iter().next().map(|(k,v)| {
+        let hm = hm.clone();
+        (vector![k.clone(), v.clone()], lazy_seq(move || {
+          Ok(list!(hm.iter().skip(1).map(|(k,v)| vector![k.clone(), v.clone()]).collect::<MalList>()))
+        }))
+      })),
+      Set(hs,_) => Ok(hs.iter().next().map(|x| {
+        let hs = hs.clone();
+        (x.clone(), lazy_seq(move || Ok(list!(hs.iter().skip(1).cloned().collect::<MalList>()))))
+      })),
+      _ => Err(MalErr::new(ErrKind::Type,
+                           &format!("{} is not a sequence", self.pr_str(true)))),
+    }
+  }
+
+  // every item of a seqable value, realizing all of a lazy seq
+  pub fn seq_vec(&self) -> Result<Vec<MalVal>,MalErr> {
+    match self {
+      List(l,_) => Ok(l.iter().cloned().collect()),
+      Vector(v,_) => Ok(v.iter().cloned().collect()),
+      Hash(hm,_) => Ok(hm.iter().map(|(k,v)| vector![k.clone(), v.clone()]).collect()),
+      Set(hs,_) => Ok(hs.iter().cloned().collect()),
+      _ => {
+        let (mut items, mut s) = (vec![], self.clone());
+        while let Some((x, rest)) = s.first_rest()? {
+          items.push(x);
+          s = rest;
+        }
+        Ok(items)
+      },
     }
   }
 
   pub fn apply(&self, args: MalArgs) -> MalRet {
     match *self {
-      Func(f,_) => f(args),
+      Func(ref nf,_) => {
+        if args.len() < nf.min || nf.max.is_some_and(|max| args.len() > max) {
+          return arity_error(args.len(), &nf.name);
+        }
+        (nf.f)(&args)
+      },
       MalFunc{eval, ref ast, ref env, ref params, ..} => {
         let a = &**ast;
         let p = &**params;
-        let fn_env = env_bind(Some(env.clone()), p.clone(), args)?;
-        Ok(eval(a.clone(), fn_env)?)
+        let fn_env = env_bind(Some(env.clone()), p.clone(), args, eval)?;
+        eval(a.clone(), fn_env).map_err(|mut e| {
+          e.trace.push(Frame{name: "fn*".to_string(), span: span_of(a)});
+          e
+        })
       }
-      _ => error("attempt to call non-function"),
-    }
-  }
-
-  pub fn keyword_q(&self) -> bool {
-    match self {
-      Str(s) if s.starts_with("\u{29e}") => true,
-      _ => false,
+      Keyword(_) => {
+        if args.is_empty() || args.len() > 2 {
+          return arity_error(args.len(), &self.pr_str(true));
+        }
+        let found = match args[0] {
+          Hash(ref hm,_) => hm.get(self).cloned(),
+          Set(ref hs,_) if hs.contains(self) => Some(self.clone()),
+          _ => None,
+        };
+        Ok(found.or_else(|| args.get(1).cloned()).unwrap_or(Nil))
+      },
+      Set(ref hs,_) => {
+        if args.len() != 1 {
+          return arity_error(args.len(), &self.pr_str(true));
+        }
+        Ok(if hs.contains(&args[0]) { args[0].clone() } else { Nil })
+      },
+      _ => error_kind(ErrKind::Type, "attempt to call non-function"),
     }
   }
 
   pub fn deref(&self) -> MalRet {
     match self {
       Atom(a) => Ok(a.borrow().clone()),
-      _       => error("attempt to deref a non-Atom"),
+      Reduced(v) => Ok((**v).clone()),
+      _       => error_kind(ErrKind::Type, "attempt to deref a non-Atom"),
     }
   }
 
@@ -137,7 +740,7 @@ impl MalVal {
         *a.borrow_mut() = new.clone();
         Ok(new.clone())
       },
-      _       => error("attempt to reset! a non-Atom"),
+      _       => error_kind(ErrKind::Type, "attempt to reset! a non-Atom"),
     }
   }
 
@@ -150,16 +753,17 @@ impl MalVal {
         *a.borrow_mut() = f.apply(fargs)?;
         Ok(a.borrow().clone())
       },
-      _ => error("attempt to swap! a non-Atom"),
+      _ => error_kind(ErrKind::Type, "attempt to swap! a non-Atom"),
     }
   }
 
   pub fn get_meta(&self) -> MalRet {
     match self {
-      List(_,meta) | Vector(_,meta) | Hash(_,meta) => Ok((&**meta).clone()),
-      Func(_,meta) => Ok((&**meta).clone()),
-      MalFunc{meta,..} => Ok((&**meta).clone()),
-      _ => error("meta not supported by type"),
+      List(_,meta) | Vector(_,meta) | Hash(_,meta) | Set(_,meta) |
+      LazySeq(_,meta) => Ok((**meta).clone()),
+      Func(_,meta) => Ok((**meta).clone()),
+      MalFunc{meta,..} => Ok((**meta).clone()),
+      _ => error_kind(ErrKind::Type, "meta not supported by type"),
     }
   }
 
@@ -168,11 +772,13 @@ impl MalVal {
       List(_, ref mut meta) |
       Vector(_, ref mut meta) |
       Hash(_, ref mut meta) |
+      Set(_, ref mut meta) |
+      LazySeq(_, ref mut meta) |
       Func(_,ref mut meta) |
       MalFunc{ref mut meta, ..} => {
-        *meta = Rc::new((&*new_meta).clone());
+        *meta = Rc::new((*new_meta).clone());
       },
-      _ => return error("with-meta not supported by type"),
+      _ => return error_kind(ErrKind::Type, "with-meta not supported by type"),
     };
     Ok(self.clone())
   }
@@ -184,48 +790,151 @@ impl PartialEq for MalVal {
       (Nil,Nil) => true,
       (Bool(ref a),Bool(ref b)) => a == b,
       (Int(ref a),Int(ref b)) => a == b,
+      (Float(ref a),Float(ref b)) => a == b,
+      (MalVal::BigInt(ref a),MalVal::BigInt(ref b)) => a == b,
+      (Int(ref a),MalVal::BigInt(ref b)) | (MalVal::BigInt(ref b),Int(ref a)) => {
+        b.to_i64() == Some(*a)
+      },
+      (MalVal::Ratio(ref a),MalVal::Ratio(ref b)) => a == b,
       (Str(ref a),Str(ref b)) => a == b,
+      (Keyword(ref a),Keyword(ref b)) => a == b,
       (Sym(ref a),Sym(ref b)) => a == b,
-      (List(ref a,_),List(ref b,_)) |
-      (Vector(ref a,_),Vector(ref b,_)) |
+      (List(ref a,_),List(ref b,_)) => a == b,
+      (Vector(ref a,_),Vector(ref b,_)) => a == b,
       (List(ref a,_),Vector(ref b,_)) |
-      (Vector(ref a,_),List(ref b,_)) => a == b,
+      (Vector(ref b,_),List(ref a,_)) => a.len() == b.len() && a.iter().eq(b.iter()),
       (Hash(ref a,_),Hash(ref b,_)) => a == b,
-      (MalFunc{..}, MalFunc{..}) => false,
+      (Set(ref a,_),Set(ref b,_)) => a == b,
+      (Tagged(ref t,ref a),Tagged(ref u,ref b)) => t == u && a == b,
+      (Regex(ref a),Regex(ref b)) => a.re.as_str() == b.re.as_str(),
+      (Func(ref a,_),Func(ref b,_)) => Rc::ptr_eq(a, b),
+      (MalFunc{ast: ref a, env: ref ea, ..}, MalFunc{ast: ref b, env: ref eb, ..}) => {
+        Rc::ptr_eq(a, b) && Rc::ptr_eq(ea, eb)
+      },
+      (Atom(ref a),Atom(ref b)) => Rc::ptr_eq(a, b),
+      (LazySeq(..), List(..)) | (LazySeq(..), Vector(..)) | (LazySeq(..), LazySeq(..)) |
+      (List(..), LazySeq(..)) | (Vector(..), LazySeq(..)) => seq_eq(self, other),
       _ => false,
     }
   }
 }
 
-pub fn func(f: fn(MalArgs) -> MalRet) -> MalVal {
-  Func(f, Rc::new(Nil))
+// compares item by item, as far as both seqs can be realized
+fn seq_eq(a: &MalVal, b: &MalVal) -> bool {
+  let (mut a, mut b) = (a.clone(), b.clone());
+  loop {
+    match (a.first_rest(), b.first_rest()) {
+      (Ok(None), Ok(None)) => return true,
+      (Ok(Some((x, ra))), Ok(Some((y, rb)))) if x == y => {
+        a = ra;
+        b = rb;
+      },
+      _ => return false,
+    }
+  }
 }
 
-pub fn _assoc(mut hm: FnvHashMap<String,MalVal>, kvs: MalArgs) -> MalRet {
-  if kvs.len() % 2 != 0 {
+// NaN is the one value not equal to itself, so it can be put in a map
+// but never found again
+impl Eq for MalVal {}
+
+// hashes agree with ==: lists and vectors hash alike, maps and sets hash the same
+// whatever their iteration order, and functions and atoms by identity
+impl StdHash for MalVal {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    match self {
+      Nil => 0u8.hash(state),
+      Bool(b) => { 1u8.hash(state); b.hash(state) },
+      Int(i) => { 2u8.hash(state); i.hash(state) },
+      Float(f) => {
+        3u8.hash(state);
+        (if *f == 0.0 { 0.0 } else { *f }).to_bits().hash(state)
+      },
+      // hashed as the Int it equals when it fits
+      MalVal::BigInt(b) => match b.to_i64() {
+        Some(i) => { 2u8.hash(state); i.hash(state) },
+        None    => { 4u8.hash(state); b.hash(state) },
+      },
+      MalVal::Ratio(r) => { 5u8.hash(state); r.hash(state) },
+      Str(s) => { 6u8.hash(state); s.hash(state) },
+      Keyword(k) => { 7u8.hash(state); k.hash(state) },
+      Sym(s) => { 8u8.hash(state); s.hash(state) },
+      List(l,_) => {
+        9u8.hash(state);
+        l.iter().for_each(|x| x.hash(state))
+      },
+      Vector(v,_) => {
+        9u8.hash(state);
+        v.iter().for_each(|x| x.hash(state))
+      },
+      LazySeq(..) => {
+        9u8.hash(state);
+        self.seq_vec().unwrap_or_default().iter().for_each(|x| x.hash(state))
+      },
+      Hash(hm,_) => {
+        10u8.hash(state);
+        hm.iter().map(|(k, v)| {
+          let mut h = FnvHasher::default();
+          k.hash(&mut h);
+          v.hash(&mut h);
+          h.finish()
+        }).fold(0u64, u64::wrapping_add).hash(state)
+      },
+      Set(hs,_) => {
+        14u8.hash(state);
+        hs.iter().map(|v| {
+          let mut h = FnvHasher::default();
+          v.hash(&mut h);
+          h.finish()
+        }).fold(0u64, u64::wrapping_add).hash(state)
+      },
+      Tagged(t,v) => { 15u8.hash(state); t.hash(state); v.hash(state) },
+      Regex(r) => { 16u8.hash(state); r.re.as_str().hash(state) },
+      Reduced(v) => { 17u8.hash(state); v.hash(state) },
+      Func(f,_) => { 11u8.hash(state); (Rc::as_ptr(f) as usize).hash(state) },
+      MalFunc{ast,..} => { 12u8.hash(state); (Rc::as_ptr(ast) as usize).hash(state) },
+      Atom(a) => { 13u8.hash(state); (Rc::as_ptr(a) as usize).hash(state) },
+    }
+  }
+}
+
+pub fn func<F>(name: &str, min: usize, max: Option<usize>, f: F) -> MalVal
+  where F: Fn(&[MalVal]) -> MalRet + 'static {
+  Func(Rc::new(NativeFn{name: name.to_string(), min, max, f: Box::new(f)}),
+       Rc::new(Nil))
+}
+
+// "ns/name" splits into its namespace and name; "/" alone is a name
+fn split_ns(s: &str) -> (Option<&str>, &str) {
+  match s.find('/') {
+    Some(i) if s != "/" => (Some(&s[..i]), &s[i+1..]),
+    _ => (None, s),
+  }
+}
+
+pub fn _assoc(mut hm: MalMap, kvs: MalArgs) -> MalRet {
+  if !kvs.len().is_multiple_of(2) {
     return error("odd number of elements")
   }
   for (k, v) in kvs.iter().tuples() {
-    match k {
-      Str(s) => { hm.insert(s.to_string(), v.clone()); },
-      _      => { return error("key is not string") },
-    }
+    hm.insert(k.clone(), v.clone());
   }
   Ok(Hash(Rc::new(hm),Rc::new(Nil)))
 }
 
-pub fn _dissoc(mut hm: FnvHashMap<String,MalVal>, ks: MalArgs) -> MalRet {
+pub fn _dissoc(mut hm: MalMap, ks: MalArgs) -> MalRet {
   for k in ks.iter() {
-    match k {
-      Str(ref s) => { hm.remove(s); },
-      _      => { return error("key is not string") },
-    }
+    hm.remove(k);
   }
   Ok(Hash(Rc::new(hm),Rc::new(Nil)))
 }
 
+pub fn hash_set(vs: MalArgs) -> MalVal {
+  Set(Rc::new(vs.into_iter().collect()),Rc::new(Nil))
+}
+
 pub fn hash_map(kvs: MalArgs) -> MalRet {
-  let hm: FnvHashMap<String,MalVal> = FnvHashMap::default();
+  let hm: MalMap = MalMap::default();
   _assoc(hm, kvs)
 }
 